@@ -1,14 +1,12 @@
 use std::io;
 use std::io::Write;
-use lisper;
 
 // Get package version defined in cargo.toml
 const PKG_VERSION:&str = env!("CARGO_PKG_VERSION");
 
 fn evaluate(exp:String, env: &mut lisper::LisperEnv) -> Result<String, lisper::LisperErr> {
-    let tokens:Vec<String> = lisper::tokenize(exp);
-    let (parsed_tokens, _) = lisper::parse(&tokens)?;
-    let eval_out = lisper::eval(parsed_tokens, env)?;
+    let forms:Vec<lisper::LisperExp> = lisper::read_all(&exp)?;
+    let eval_out = lisper::eval_program(&forms, env)?;
 
     Ok(eval_out.to_string())
 }
@@ -44,9 +42,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>>{
                 // Evaluate the string as a lisper expression
                 match evaluate(expr, env) {
                     Ok(res) => println!("{}", res),
-                    Err(e) => match e {
-                    lisper::LisperErr::Reason(msg) => println!("Error = {}", msg),
-                    },
+                    Err(e) => println!("{}", e),
                 }
             }
         }
@@ -0,0 +1,470 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// An integer of any size. The magnitude is stored in base 2^32 digits, least
+// significant first, without leading zero digits. Zero has no digits and is
+// never negative.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>
+}
+
+// 10^9, the largest power of ten that fits in a digit
+const DECIMAL_BASE: u32 = 1_000_000_000;
+
+impl BigInt {
+    pub fn zero() -> BigInt {
+        BigInt { negative: false, digits: vec![] }
+    }
+
+    pub fn one() -> BigInt {
+        BigInt::from(1)
+    }
+
+    // Creates a number from a sign and digits that may have leading zeros
+    fn from_parts(negative: bool, mut digits: Vec<u32>) -> BigInt {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        let negative = negative && !digits.is_empty();
+        BigInt { negative, digits }
+    }
+
+    // Parses a decimal integer with an optional sign, e.g. "-123"
+    pub fn parse(s: &str) -> Option<BigInt> {
+        let (negative, unsigned) = match s.as_bytes().first()? {
+            b'-' => (true, &s[1..]),
+            b'+' => (false, &s[1..]),
+            _ => (false, s),
+        };
+        if unsigned.is_empty() || !unsigned.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        // Reads nine decimal digits at a time, the first chunk may be shorter
+        let mut digits: Vec<u32> = vec![];
+        let first = unsigned.len() % 9;
+        let mut start = 0;
+        for end in (first..=unsigned.len()).step_by(9).filter(|end| *end > 0) {
+            let chunk: u32 = unsigned[start..end].parse().ok()?;
+            mul_small_add(&mut digits, DECIMAL_BASE, chunk);
+            start = end;
+        }
+        Some(BigInt::from_parts(negative, digits))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt { negative: false, digits: self.digits.clone() }
+    }
+
+    // The number of bits in the magnitude
+    pub fn bits(&self) -> usize {
+        match self.digits.last() {
+            Some(top) => self.digits.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    // The value as an i64, if it fits
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let magnitude = self.digits.iter().rev().fold(0u64, |acc, d| (acc << 32) | *d as u64);
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    // The nearest float, or infinity if the number is too large
+    pub fn to_f64(&self) -> f64 {
+        // The top 64 bits decide the float, the rest only scale it
+        let shift = self.bits().saturating_sub(64);
+        let abs = self.abs();
+        let top = abs.shr(shift);
+        // Bits below the top 64 only matter when they break a tie, so they
+        // are folded into the lowest bit
+        let sticky = abs != top.shl(shift);
+        let top = top.digits.iter().rev().fold(0u64, |acc, d| (acc << 32) | *d as u64);
+        let magnitude = (top | sticky as u64) as f64 * 2f64.powi(shift.min(i32::MAX as usize) as i32);
+        if self.negative { -magnitude } else { magnitude }
+    }
+
+    // The quotient rounded towards zero and the remainder, which has the sign
+    // of self. Panics if other is zero.
+    pub fn div_rem(&self, other: &BigInt) -> (BigInt, BigInt) {
+        assert!(!other.is_zero(), "division by zero");
+        let (quotient, remainder) = div_rem_digits(&self.digits, &other.digits);
+        (
+            BigInt::from_parts(self.negative != other.negative, quotient),
+            BigInt::from_parts(self.negative, remainder)
+        )
+    }
+
+    // The quotient rounded down, towards negative infinity
+    pub fn div_floor(&self, other: &BigInt) -> BigInt {
+        let (quotient, remainder) = self.div_rem(other);
+        if !remainder.is_zero() && remainder.negative != other.negative {
+            &quotient - &BigInt::one()
+        } else {
+            quotient
+        }
+    }
+
+    // The greatest common divisor, always non-negative
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.is_zero() {
+            let (_, remainder) = a.div_rem(&b);
+            a = b;
+            b = remainder;
+        }
+        a
+    }
+
+    pub fn pow(&self, mut exponent: u64) -> BigInt {
+        let mut base = self.clone();
+        let mut result = BigInt::one();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    // Multiplies by 2^bits
+    pub fn shl(&self, bits: usize) -> BigInt {
+        let mut digits = vec![0; bits / 32];
+        digits.extend(shift_left(&self.digits, (bits % 32) as u32));
+        BigInt::from_parts(self.negative, digits)
+    }
+
+    // Divides the magnitude by 2^bits, rounding towards zero
+    pub fn shr(&self, bits: usize) -> BigInt {
+        let skip = (bits / 32).min(self.digits.len());
+        BigInt::from_parts(self.negative, shift_right(&self.digits[skip..], (bits % 32) as u32))
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> BigInt {
+        let magnitude = n.unsigned_abs();
+        BigInt::from_parts(n < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_digits(&self.digits, &other.digits),
+            (true, true) => cmp_digits(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Add<&'a BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, add_digits(&self.digits, &other.digits));
+        }
+        // Different signs, so the smaller magnitude is taken from the larger
+        match cmp_digits(&self.digits, &other.digits) {
+            Ordering::Less => BigInt::from_parts(other.negative, sub_digits(&other.digits, &self.digits)),
+            _ => BigInt::from_parts(self.negative, sub_digits(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl<'a> Sub<&'a BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl<'a> Mul<&'a BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(self.negative != other.negative, mul_digits(&self.digits, &other.digits))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.digits.clone())
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Splits off nine decimal digits at a time, least significant first
+        let mut chunks: Vec<u32> = vec![];
+        let mut rest = self.digits.clone();
+        while !rest.is_empty() {
+            chunks.push(div_small(&mut rest, DECIMAL_BASE));
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(top) = chunks.next() {
+            write!(f, "{}", top)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut res = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, digit) in long.iter().enumerate() {
+        let sum = *digit as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        res.push(sum as u32);
+        carry = sum >> 32;
+    }
+    res.push(carry as u32);
+    res
+}
+
+// Subtracts b from a, a must be at least as large as b
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, digit) in a.iter().enumerate() {
+        let diff = *digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        res.push(diff as u32);
+        borrow = (diff < 0) as i64;
+    }
+    res
+}
+
+fn mul_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let product = *x as u64 * *y as u64 + res[i + j] as u64 + carry;
+            res[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        res[i + b.len()] = carry as u32;
+    }
+    res
+}
+
+// Multiplies the digits by a small number and adds another, in place
+fn mul_small_add(digits: &mut Vec<u32>, mul: u32, add: u32) {
+    let mut carry = add as u64;
+    for digit in digits.iter_mut() {
+        let product = *digit as u64 * mul as u64 + carry;
+        *digit = product as u32;
+        carry = product >> 32;
+    }
+    if carry > 0 {
+        digits.push(carry as u32);
+    }
+}
+
+// Divides the digits by a small number in place, returns the remainder
+fn div_small(digits: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+    for digit in digits.iter_mut().rev() {
+        let current = (remainder << 32) | *digit as u64;
+        *digit = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    remainder as u32
+}
+
+// Shifts left by less than a digit, the result has one more digit
+fn shift_left(digits: &[u32], shift: u32) -> Vec<u32> {
+    let mut res = Vec::with_capacity(digits.len() + 1);
+    let mut carry = 0u32;
+    for digit in digits {
+        res.push((digit << shift) | carry);
+        carry = if shift == 0 { 0 } else { digit >> (32 - shift) };
+    }
+    res.push(carry);
+    res
+}
+
+// Shifts right by less than a digit
+fn shift_right(digits: &[u32], shift: u32) -> Vec<u32> {
+    (0..digits.len()).map(|i| {
+        let high = match digits.get(i + 1) {
+            Some(next) if shift > 0 => next << (32 - shift),
+            _ => 0,
+        };
+        (digits[i] >> shift) | high
+    }).collect()
+}
+
+// Long division of the magnitudes, returns the quotient and remainder. This
+// is algorithm D from Knuth's The Art of Computer Programming, vol. 2.
+fn div_rem_digits(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_digits(a, b) == Ordering::Less {
+        return (vec![], a.to_vec());
+    }
+    if b.len() == 1 {
+        let mut quotient = a.to_vec();
+        let remainder = div_small(&mut quotient, b[0]);
+        return (quotient, vec![remainder]);
+    }
+
+    // Shifts both so the top bit of the divisor is set, which keeps the
+    // estimate of each quotient digit at most two too large
+    let shift = b[b.len() - 1].leading_zeros();
+    let mut divisor = shift_left(b, shift);
+    divisor.pop();
+    let mut rem = shift_left(a, shift);
+    let n = divisor.len();
+    let m = rem.len() - n;
+    let (top, second) = (divisor[n - 1] as u64, divisor[n - 2] as u64);
+    let mut quotient = vec![0u32; m];
+
+    for j in (0..m).rev() {
+        let current = ((rem[j + n] as u64) << 32) | rem[j + n - 1] as u64;
+        let mut estimate = current / top;
+        let mut estimate_rem = current % top;
+        while estimate >> 32 != 0 || estimate * second > ((estimate_rem << 32) | rem[j + n - 2] as u64) {
+            estimate -= 1;
+            estimate_rem += top;
+            if estimate_rem >> 32 != 0 {
+                break;
+            }
+        }
+
+        // Subtracts estimate times the divisor from the current digits
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = estimate * divisor[i] as u64 + carry;
+            carry = product >> 32;
+            let diff = rem[i + j] as i64 - borrow - (product & 0xffff_ffff) as i64;
+            rem[i + j] = diff as u32;
+            borrow = (diff < 0) as i64;
+        }
+        let diff = rem[j + n] as i64 - borrow - carry as i64;
+        rem[j + n] = diff as u32;
+
+        // The estimate was one too large, so the divisor is added back
+        if diff < 0 {
+            estimate -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = rem[i + j] as u64 + divisor[i] as u64 + carry;
+                rem[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            rem[j + n] = rem[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = estimate as u32;
+    }
+
+    (quotient, shift_right(&rem[..n], shift))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        BigInt::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parse_and_print() {
+        for s in ["0", "7", "-42", "4294967296", "123456789012345678901234567890", "-1000000000000000000000"].iter() {
+            assert_eq!(big(s).to_string(), *s);
+        }
+        assert_eq!(big("+0012").to_string(), "12");
+        assert_eq!(big("-0").to_string(), "0");
+        assert!(BigInt::parse("").is_none());
+        assert!(BigInt::parse("-").is_none());
+        assert!(BigInt::parse("12a").is_none());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!((&a * &b).to_string(), "-121932631137021795226185032733622923332237463801111263526900");
+        assert_eq!(BigInt::from(2).pow(100).to_string(), "1267650600228229401496703205376");
+        assert_eq!(BigInt::from(12).gcd(&BigInt::from(-18)), BigInt::from(6));
+        assert_eq!(BigInt::from(-7).div_floor(&BigInt::from(2)), BigInt::from(-4));
+    }
+
+    #[test]
+    fn division_matches_i128() {
+        let values: [i128; 8] = [
+            0, 1, -3, 4294967295, 18446744073709551617, -79228162514264337593543950336,
+            170141183460469231731687303715884105727, -85070591730234615865843651857942052864,
+        ];
+        for x in values.iter() {
+            for y in values.iter().filter(|y| **y != 0) {
+                let (q, r) = big(&x.to_string()).div_rem(&big(&y.to_string()));
+                assert_eq!(q.to_string(), (x / y).to_string(), "{} / {}", x, y);
+                assert_eq!(r.to_string(), (x % y).to_string(), "{} % {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(BigInt::from(i64::MAX).to_i64(), Some(i64::MAX));
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("9007199254740993").to_f64(), 9007199254740992.0);
+        assert_eq!(big("-123456789012345678901234567890").to_f64(), -1.2345678901234568e29);
+        assert_eq!(BigInt::one().shl(1100).to_f64(), f64::INFINITY);
+        assert_eq!(BigInt::one().shl(100).shr(98), BigInt::from(4));
+    }
+}
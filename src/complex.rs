@@ -0,0 +1,194 @@
+use std::fmt;
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::numbers::format_float;
+
+// A complex number with float parts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn from_polar(magnitude: f64, angle: f64) -> Complex {
+        Complex::new(magnitude * angle.cos(), magnitude * angle.sin())
+    }
+
+    // The distance from zero
+    pub fn norm(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    // The angle from the positive real axis, from -π to π
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn exp(self) -> Complex {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    // The principal natural logarithm, with the imaginary part from -π to π
+    pub fn ln(self) -> Complex {
+        Complex::new(self.norm().ln(), self.arg())
+    }
+
+    // The principal square root, the one with a non-negative real part
+    pub fn sqrt(self) -> Complex {
+        let norm = self.norm();
+        let re = ((norm + self.re) / 2.0).sqrt();
+        let im = ((norm - self.re) / 2.0).sqrt();
+        Complex::new(re, if self.im.is_sign_negative() { -im } else { im })
+    }
+
+    // Raises to an integer power by repeated squaring, which is exact for
+    // small whole parts unlike powc
+    pub fn powi(self, exponent: i64) -> Complex {
+        let (mut base, mut power, mut result) = (self, exponent.unsigned_abs(), Complex::new(1.0, 0.0));
+        while power > 0 {
+            if power & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            power >>= 1;
+        }
+        if exponent < 0 {
+            Complex::new(1.0, 0.0) / result
+        } else {
+            result
+        }
+    }
+
+    // Raises to a complex power, using the principal logarithm
+    pub fn powc(self, exponent: Complex) -> Complex {
+        if self.re == 0.0 && self.im == 0.0 {
+            let one = exponent.re == 0.0 && exponent.im == 0.0;
+            return Complex::new(if one { 1.0 } else { 0.0 }, 0.0);
+        }
+        (exponent * self.ln()).exp()
+    }
+
+    pub fn sin(self) -> Complex {
+        Complex::new(self.re.sin() * self.im.cosh(), self.re.cos() * self.im.sinh())
+    }
+
+    pub fn cos(self) -> Complex {
+        Complex::new(self.re.cos() * self.im.cosh(), -self.re.sin() * self.im.sinh())
+    }
+
+    pub fn tan(self) -> Complex {
+        self.sin() / self.cos()
+    }
+
+    pub fn sinh(self) -> Complex {
+        Complex::new(self.re.sinh() * self.im.cos(), self.re.cosh() * self.im.sin())
+    }
+
+    pub fn cosh(self) -> Complex {
+        Complex::new(self.re.cosh() * self.im.cos(), self.re.sinh() * self.im.sin())
+    }
+
+    pub fn tanh(self) -> Complex {
+        self.sinh() / self.cosh()
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let scale = other.re * other.re + other.im * other.im;
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / scale,
+            (self.im * other.re - self.re * other.im) / scale
+        )
+    }
+}
+
+// Printed as a literal, like 3+4i, 1.5-2i or 1+infi. NaN is printed without
+// its sign, so it always gets a plus.
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.im.is_sign_negative() && !self.im.is_nan() { "" } else { "+" };
+        write!(f, "{}{}{}i", format_float(self.re), sign, format_float(self.im))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks that two complex numbers are equal, up to rounding errors
+    fn assert_close(a: Complex, b: Complex) {
+        assert!((a - b).norm() < 1e-12, "{} is not close to {}", a, b);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let (a, b) = (Complex::new(3.0, 4.0), Complex::new(1.0, -2.0));
+        assert_eq!(a + b, Complex::new(4.0, 2.0));
+        assert_eq!(a - b, Complex::new(2.0, 6.0));
+        assert_eq!(a * b, Complex::new(11.0, -2.0));
+        assert_close(a / b, Complex::new(-1.0, 2.0));
+        assert_eq!(a.norm(), 5.0);
+        assert_eq!(Complex::new(-1.0, 0.0).arg(), std::f64::consts::PI);
+    }
+
+    #[test]
+    fn functions() {
+        let i = Complex::new(0.0, 1.0);
+        assert_close((i * Complex::new(std::f64::consts::PI, 0.0)).exp(), Complex::new(-1.0, 0.0));
+        assert_close(Complex::new(-4.0, 0.0).sqrt(), Complex::new(0.0, 2.0));
+        assert_close(Complex::new(3.0, -4.0).sqrt(), Complex::new(2.0, -1.0));
+        assert_close(i.powc(i), Complex::new((-std::f64::consts::FRAC_PI_2).exp(), 0.0));
+        assert_close(i.ln(), Complex::new(0.0, std::f64::consts::FRAC_PI_2));
+        assert_close(i.sin(), Complex::new(0.0, 1f64.sinh()));
+        assert_close(i.cos(), Complex::new(1f64.cosh(), 0.0));
+        assert_close(i.tanh(), Complex::new(0.0, 1f64.tan()));
+        assert_eq!(Complex::new(0.0, 0.0).powc(i), Complex::new(0.0, 0.0));
+        assert_eq!(Complex::new(1.0, 1.0).powi(2), Complex::new(0.0, 2.0));
+        assert_eq!(i.powi(-1), Complex::new(0.0, -1.0));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Complex::new(3.0, 4.0).to_string(), "3+4i");
+        assert_eq!(Complex::new(1.5, -2.0).to_string(), "1.5-2i");
+        assert_eq!(Complex::new(-0.0, 1.0).to_string(), "-0+1i");
+        assert_eq!(Complex::new(f64::NAN, f64::NEG_INFINITY).to_string(), "nan-infi");
+        assert_eq!(Complex::new(1.0, -f64::NAN).to_string(), "1+nani");
+        assert_eq!(Complex::new(1e300, -1e-300).to_string(), "1e300-1e-300i");
+    }
+}
@@ -1,810 +1,3536 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt;
 use std::error;
-
-// Represents an individual Lisp expresion
+use std::rc::Rc;
+
+mod bigint;
+mod complex;
+mod lists;
+mod macros;
+mod math;
+mod numbers;
+mod rational;
+mod strings;
+mod syntax_rules;
+
+pub use bigint::BigInt;
+pub use complex::Complex;
+pub use rational::Rational;
+
+// Represents an individual Lisp expresion. The larger values are kept behind
+// an Rc, so expressions stay small and cheap to move around in eval.
 #[derive(Clone, Debug)]
 pub enum LisperExp {
     Bool(bool),
     Symbol(String),
+    Int(i64),
+    BigInt(BigInt),
+    Rational(Rc<Rational>),
     Number(f64),
-    List(Vec<LisperExp>)
+    Complex(Complex),
+    Str(String),
+    List(LisperList),
+    Func(Rc<LisperFunc>),
+    Lambda(Rc<LisperLambda>),
+    Macro(Rc<LisperLambda>),
+    SyntaxRules(Rc<LisperSyntaxRules>)
 }
 
-// Used for to_string
-impl fmt::Display for LisperExp {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let str:String = match self {
-            LisperExp::Symbol(s) => s.to_string(),
-            LisperExp::Number(n) => n.to_string(),
-            LisperExp::Bool(b) => b.to_string(),
-            LisperExp::List(list) => {
-                let items:Vec<String> = list.iter().map(|item| item.to_string()).collect();
-                format!("({})", items.join(","))
-            },
-        };
-        
-        write!(f, "{}", str)
-    }
-}
+// The signature of builtin functions implemented in Rust
+pub type LisperNativeFn = dyn Fn(&[LisperExp], &mut LisperEnv) -> Result<LisperExp, LisperErr>;
 
-// An error type for the Lisp interperter
-#[derive(Debug)]
-pub enum LisperErr {
-    Reason(String)
+// The number of arguments a builtin function accepts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LisperArity {
+    Exact(usize),
+    AtLeast(usize),
+    Range(usize, usize)
 }
 
-impl error::Error for LisperErr {}
+impl LisperArity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            LisperArity::Exact(n) => count == n,
+            LisperArity::AtLeast(min) => count >= min,
+            LisperArity::Range(min, max) => count >= min && count <= max,
+        }
+    }
+}
 
-impl fmt::Display for LisperErr {
+impl fmt::Display for LisperArity {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            LisperErr::Reason(reason) => write!(f, "{}", reason),
+            LisperArity::Exact(n) => write!(f, "{}", n),
+            LisperArity::AtLeast(min) => write!(f, "at least {}", min),
+            LisperArity::Range(min, max) => write!(f, "{} to {}", min, max),
         }
     }
 }
 
-//  Represents the context where a Lisp expression executes
+// A builtin function implemented in Rust. The function is reference counted,
+// so it can be a closure that captures state from the host application.
 #[derive(Clone)]
-pub struct LisperEnv {
-    pub data: HashMap<String, fn(&LisperExp) -> LisperExp>
+pub struct LisperFunc {
+    pub name: String,
+    pub arity: LisperArity,
+    pub doc: String,
+    func: Rc<LisperNativeFn>
 }
 
-// Breaks an input string into separate one character tokens
-pub fn tokenize(expr: String) -> Vec<String> {
-    expr
-        .replace("(", "( ")
-        .replace(")", " )")
-        .split_whitespace()
-        .map(|x| x.to_string())
-        .collect()
+impl LisperFunc {
+    pub fn new<F>(name: &str, arity: LisperArity, doc: &str, func: F) -> LisperFunc
+    where F: Fn(&[LisperExp], &mut LisperEnv) -> Result<LisperExp, LisperErr> + 'static {
+        LisperFunc {
+            name: name.to_string(),
+            arity,
+            doc: doc.to_string(),
+            func: Rc::new(func)
+        }
+    }
+
+    // Checks the number of arguments, then runs the function
+    pub fn call(&self, args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+        if !self.arity.accepts(args.len()) {
+            return Err(LisperErr::new(
+                LisperErrKind::ArityError,
+                format!("{} expects {} arguments, got {}.", self.name, self.arity, args.len())
+            ));
+        }
+        (self.func)(args, env)
+    }
 }
 
-// Parses an array of string tokens and creates corresponding LisperExp objects
-pub fn parse<'a>(tokens: &'a [String]) -> Result<(LisperExp, &'a [String]), LisperErr> {
-    let (first, rest) = tokens.split_first()
-        .ok_or(
-            LisperErr::Reason("Could not get token".to_string())
-        )?;
+impl fmt::Debug for LisperFunc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LisperFunc")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+// A user defined function, created with (fn (params...) body...). Macros
+// created with defmacro are lambdas too.
+#[derive(Clone, Debug)]
+pub struct LisperLambda {
+    // Set when the function is bound with def or defn, used in error stacks
+    pub name: Option<String>,
+    pub params: Rc<Vec<String>>,
+    // The parameter after &, bound to a list of the remaining arguments
+    pub rest: Option<String>,
+    pub body: Rc<Vec<LisperExp>>,
+    // The environment the function was defined in
+    pub env: LisperEnv
+}
 
-    let mut parsed_result: Vec<LisperExp> = vec![];
+// A hygienic macro, created with
+// (define-syntax name (syntax-rules (literals...) (pattern template)...))
+#[derive(Clone, Debug)]
+pub struct LisperSyntaxRules {
+    pub name: String,
+    pub literals: Rc<Vec<String>>,
+    pub rules: Rc<Vec<(LisperExp, LisperExp)>>,
+    // The environment the macro was defined in, free symbols in the
+    // templates refer to their bindings there
+    pub env: LisperEnv,
+    // Unique to the macro, marks the symbols its templates refer to
+    mark: usize
+}
 
-    match first.as_str() {
-        "(" => {
-            let mut more = rest;
-            loop {
-                let (next, more_next) = more.split_first()
-                    .ok_or(
-                        LisperErr::Reason("Error reading token, missing ).".to_string())
-                    )?;
-                if next == ")" {
-                    return Ok((LisperExp::List(parsed_result), more_next))
-                }
-                let (exp, new_more) = parse(&more)?;
-                parsed_result.push(exp);
-                more = new_more;
+// Values are equal when they have the same type and contents, lists are
+// compared item by item. Functions and macros are only equal to themselves.
+// The items of lists are queued rather than compared recursively, so deeply
+// nested lists can't overflow the stack.
+impl PartialEq for LisperExp {
+    fn eq(&self, other: &LisperExp) -> bool {
+        let mut pending = vec![(self, other)];
+        while let Some(pair) = pending.pop() {
+            if !shallow_eq(pair, &mut pending) {
+                return false;
             }
-        },
-        ")" => {
-            return Err(LisperErr::Reason("Parsing error, found unexpected ).".to_string()))
-        },
-        _ => {
-            let parsed_token:LisperExp = parse_token(&first);
-            return Ok((parsed_token, rest))
         }
-    };
+        true
+    }
 }
 
-// Parses an individual token and creates either a Number of Symbol LisperExp
-fn parse_token(token: &str) -> LisperExp {
-    if let Result::Ok(parsed_bool) = token.parse::<bool>() {
-        LisperExp::Bool(parsed_bool)
-    } else if let Result::Ok(parsed_value) = token.parse::<f64>() {
-        LisperExp::Number(parsed_value)
-    } else {
-        LisperExp::Symbol(token.to_string().clone())
+// Compares two values, queueing the items of lists to compare later
+fn shallow_eq<'a>(pair: (&'a LisperExp, &'a LisperExp), pending: &mut Vec<(&'a LisperExp, &'a LisperExp)>) -> bool {
+    match pair {
+        (LisperExp::Bool(a), LisperExp::Bool(b)) => a == b,
+        (LisperExp::Symbol(a), LisperExp::Symbol(b)) => a == b,
+        (LisperExp::Int(a), LisperExp::Int(b)) => a == b,
+        (LisperExp::BigInt(a), LisperExp::BigInt(b)) => a == b,
+        (LisperExp::Rational(a), LisperExp::Rational(b)) => a == b,
+        (LisperExp::Number(a), LisperExp::Number(b)) => a == b,
+        (LisperExp::Complex(a), LisperExp::Complex(b)) => a == b,
+        (LisperExp::Str(a), LisperExp::Str(b)) => a == b,
+        (LisperExp::List(a), LisperExp::List(b)) => {
+            if !a.ptr_eq(b) {
+                pending.extend(a.iter().zip(b.iter()));
+            }
+            a.len() == b.len()
+        },
+        (LisperExp::Func(a), LisperExp::Func(b)) => std::ptr::addr_eq(Rc::as_ptr(&a.func), Rc::as_ptr(&b.func)),
+        (LisperExp::Lambda(a), LisperExp::Lambda(b)) | (LisperExp::Macro(a), LisperExp::Macro(b)) => {
+            Rc::ptr_eq(&a.body, &b.body) && Rc::ptr_eq(&a.env.data, &b.env.data)
+        },
+        (LisperExp::SyntaxRules(a), LisperExp::SyntaxRules(b)) => a.mark == b.mark,
+        _ => false,
     }
 }
 
-// Create a default environment containing fundamental functions
-pub fn create_default_env() -> LisperEnv {
-    let mut env_data: HashMap<String, fn(&LisperExp) -> LisperExp> = HashMap::new();
+// The items of a list. They are shared between copies of the list, so
+// cloning a list or looking it up in an env doesn't copy the items.
+#[derive(Clone, Default)]
+pub struct LisperList(Rc<LisperListData>);
+
+// The items of a list, and the span of the source it was read from. Lists
+// built at run time have no span.
+#[derive(Default)]
+struct LisperListData {
+    items: Vec<LisperExp>,
+    span: Option<LisperSpan>
+}
 
-    // Basic math functions
-    env_data.insert("+".to_string(), add);
-    env_data.insert("add".to_string(), add);
-    env_data.insert("-".to_string(), sub);
-    env_data.insert("sub".to_string(), sub);
-    env_data.insert("*".to_string(), mul);
-    env_data.insert("mul".to_string(), mul);
-    env_data.insert("/".to_string(), div);
-    env_data.insert("div".to_string(), div);
-    env_data.insert("%".to_string(), modulus);
-    env_data.insert("mod".to_string(), modulus);
+impl LisperList {
+    // A list read from the source, errors in it point at the span
+    pub fn spanned(items: Vec<LisperExp>, span: LisperSpan) -> LisperList {
+        LisperList(Rc::new(LisperListData { items, span: Some(span) }))
+    }
 
-    // Comparators
-    env_data.insert("<".to_string(), less_than);
-    env_data.insert(">".to_string(), more_than);
-    env_data.insert("=".to_string(), equals);
-    env_data.insert("==".to_string(), equals);
-    env_data.insert("<=".to_string(), less_or_equal);
-    env_data.insert(">=".to_string(), more_or_equal);
+    // Where the list was read from, if it came from the source
+    pub fn span(&self) -> Option<LisperSpan> {
+        self.0.span
+    }
 
-    // Trig functions
-    env_data.insert("sin".to_string(), sin);
-    env_data.insert("cos".to_string(), cos);
-    env_data.insert("tan".to_string(), tan);
+    // True if both lists share the same items
+    pub fn ptr_eq(&self, other: &LisperList) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
 
-    env_data.insert("pi".to_string(), |_| -> LisperExp {
-        LisperExp::Number(core::f64::consts::PI)
-    });
+impl std::ops::Deref for LisperList {
+    type Target = [LisperExp];
 
-    LisperEnv {data: env_data}
+    fn deref(&self) -> &[LisperExp] {
+        &self.0.items
+    }
 }
 
-// Evaluates a given Lisp expression, and returns a new one with the result.
-pub fn eval(exp: LisperExp, env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
-    match exp {
-        LisperExp::List(list) => {
-            // Split the symbol from the arguments
-            let (sym, args) = list.split_first()
-            .ok_or(
-                LisperErr::Reason("Error reading expression".to_string())
-            )?;
-                        
-            // Evaluate each argument
-            let mut evaluated_args: Vec<LisperExp> = vec![];
-            for arg in args.iter() {
-                evaluated_args.push(eval(arg.clone(), env)?);
-            }
+impl From<Vec<LisperExp>> for LisperList {
+    fn from(items: Vec<LisperExp>) -> LisperList {
+        LisperList(Rc::new(LisperListData { items, span: None }))
+    }
+}
 
-            // Get the env function based on the symbol
-            let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get(&sym.to_string())
-            .ok_or(
-                LisperErr::Reason("Error, function not found.".to_string())
-            )?;
-            
-            // Run the function with the args, and return the result
-            Ok(lisper_func(&LisperExp::List(evaluated_args)))
-        },
-        LisperExp::Number(num) => {
-            // If it's just a number, then return the number
-            Ok(LisperExp::Number(num))
-        },
-        LisperExp::Symbol(sym) => {
-            let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get(&sym.to_string())
-            .ok_or (
-                // We shouldn't be evaluating function symbols here, since they should be
-                // wrapped in lists above. Something is wrong, return an error.
-                LisperErr::Reason("Eval issue, not a real expression".to_string())
-            )?;
-
-            // This is actually a def, so return the value 
-            Ok(lisper_func(&LisperExp::Bool(true)))
-        },
-        LisperExp::Bool(b) => {
-            Ok(LisperExp::Bool(b))
-        }
+impl std::iter::FromIterator<LisperExp> for LisperList {
+    fn from_iter<I: IntoIterator<Item = LisperExp>>(iter: I) -> LisperList {
+        LisperList::from(iter.into_iter().collect::<Vec<LisperExp>>())
+    }
+}
+
+impl<'a> IntoIterator for &'a LisperList {
+    type Item = &'a LisperExp;
+    type IntoIter = std::slice::Iter<'a, LisperExp>;
+
+    fn into_iter(self) -> std::slice::Iter<'a, LisperExp> {
+        self.0.items.iter()
     }
 }
 
-fn add(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum += n;
+impl fmt::Debug for LisperList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.items.fmt(f)
+    }
+}
+
+// Dropping a deeply nested list would recurse once for every level, so the
+// items of lists that aren't shared are moved out and dropped one at a time
+impl Drop for LisperList {
+    fn drop(&mut self) {
+        let mut pending = match Rc::get_mut(&mut self.0) {
+            Some(data) if data.items.iter().any(|item| matches!(item, LisperExp::List(_))) => std::mem::take(&mut data.items),
+            _ => return,
+        };
+        while let Some(exp) = pending.pop() {
+            if let LisperExp::List(mut list) = exp {
+                if let Some(data) = Rc::get_mut(&mut list.0) {
+                    pending.append(&mut data.items);
                 }
             }
         }
     }
-    return LisperExp::Number(sum)
 }
 
-fn sub(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum -= n;
+impl LisperExp {
+    // A total order over all values, used by sort. Values of different types
+    // are ordered by type, bools first, then numbers, strings, symbols, lists,
+    // and last functions and macros, which are not ordered among themselves.
+    // Lists are walked with a stack of their items rather than recursively.
+    pub fn total_cmp(&self, other: &LisperExp) -> Ordering {
+        let mut pending: Vec<(std::slice::Iter<LisperExp>, std::slice::Iter<LisperExp>)> = vec![];
+        let (mut a, mut b) = (self, other);
+        loop {
+            let ord = match (a, b) {
+                (LisperExp::List(x), LisperExp::List(y)) => {
+                    pending.push((x.iter(), y.iter()));
+                    Ordering::Equal
+                },
+                _ => a.shallow_cmp(b),
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            // The next pair of items, a shorter list comes first
+            loop {
+                let Some((xs, ys)) = pending.last_mut() else {
+                    return Ordering::Equal;
+                };
+                match (xs.next(), ys.next()) {
+                    (Some(x), Some(y)) => {
+                        (a, b) = (x, y);
+                        break;
+                    },
+                    (None, None) => {
+                        pending.pop();
+                    },
+                    (None, Some(_)) => return Ordering::Less,
+                    (Some(_), None) => return Ordering::Greater,
                 }
             }
         }
     }
-    return LisperExp::Number(sum)
+
+    // Orders two values that aren't both lists
+    fn shallow_cmp(&self, other: &LisperExp) -> Ordering {
+        if let (Some(a), Some(b)) = (numbers::Num::from_exp(self), numbers::Num::from_exp(other)) {
+            return numbers::total_cmp(&a, &b);
+        }
+        match (self, other) {
+            (LisperExp::Bool(a), LisperExp::Bool(b)) => a.cmp(b),
+            (LisperExp::Str(a), LisperExp::Str(b)) | (LisperExp::Symbol(a), LisperExp::Symbol(b)) => a.cmp(b),
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+
+    fn type_rank(&self) -> usize {
+        match self {
+            LisperExp::Bool(_) => 0,
+            LisperExp::Int(_) | LisperExp::BigInt(_) | LisperExp::Rational(_) | LisperExp::Number(_) | LisperExp::Complex(_) => 1,
+            LisperExp::Str(_) => 2,
+            LisperExp::Symbol(_) => 3,
+            LisperExp::List(_) => 4,
+            LisperExp::Func(_) | LisperExp::Lambda(_) | LisperExp::Macro(_) | LisperExp::SyntaxRules(_) => 5,
+        }
+    }
+
+    // The name of the type of the expression, used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            LisperExp::Bool(_) => "bool",
+            LisperExp::Symbol(_) => "symbol",
+            LisperExp::Int(_) | LisperExp::BigInt(_) | LisperExp::Rational(_) | LisperExp::Number(_) | LisperExp::Complex(_) => "number",
+            LisperExp::Str(_) => "string",
+            LisperExp::List(_) => "list",
+            LisperExp::Func(_) | LisperExp::Lambda(_) => "function",
+            LisperExp::Macro(_) | LisperExp::SyntaxRules(_) => "macro",
+        }
+    }
+}
+
+impl LisperExp {
+    // Prints the expression for people to read. Strings are shown as they
+    // are, without quotes or escapes, so the output may not read back.
+    pub fn display(&self) -> String {
+        print_exp(self, false)
+    }
+}
+
+// Prints an expression so that parse reads it back as the same expression
+pub fn print(exp: &LisperExp) -> String {
+    print_exp(exp, true)
+}
+
+// Used for to_string, prints the same as print
+impl fmt::Display for LisperExp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", print_exp(self, true))
+    }
+}
+
+// A step in printing an expression
+enum LisperPrint<'a> {
+    Exp(&'a LisperExp),
+    Text(&'static str)
 }
 
-fn mul(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum *= n;
+// Prints an expression. Readable output escapes strings, so it can be read
+// back by parse. Lists are printed with a stack of the steps left rather
+// than recursively, so deeply nested lists can't overflow the stack.
+fn print_exp(exp: &LisperExp, readable: bool) -> String {
+    let mut out = String::new();
+    let mut pending = vec![LisperPrint::Exp(exp)];
+    while let Some(step) = pending.pop() {
+        match step {
+            LisperPrint::Text(text) => out.push_str(text),
+            LisperPrint::Exp(LisperExp::List(list)) => {
+                // (quote x) and the like are printed with the reader shorthand
+                if let Some((prefix, quoted)) = reader_shorthand(list, readable) {
+                    out.push_str(prefix);
+                    pending.push(LisperPrint::Exp(quoted));
+                    continue;
                 }
-            }
+                out.push('(');
+                pending.push(LisperPrint::Text(")"));
+                for (i, item) in list.iter().enumerate().rev() {
+                    pending.push(LisperPrint::Exp(item));
+                    if i > 0 {
+                        pending.push(LisperPrint::Text(" "));
+                    }
+                }
+            },
+            LisperPrint::Exp(exp) => out.push_str(&print_atom(exp, readable)),
         }
     }
-    return LisperExp::Number(sum)
+    out
 }
 
-fn div(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum /= n;
-                }
+// The reader shorthand a list like (quote x) is printed with, and the
+// expression that follows it
+fn reader_shorthand(list: &[LisperExp], readable: bool) -> Option<(&'static str, &LisperExp)> {
+    let [LisperExp::Symbol(sym), quoted] = list else {
+        return None;
+    };
+    let prefix = match sym.as_str() {
+        "quote" => "'",
+        "quasiquote" => "`",
+        "unquote" => ",",
+        "unquote-splicing" => ",@",
+        _ => return None,
+    };
+    // ,@x would read as unquote-splicing, lists never print starting with @
+    if prefix == "," && !matches!(quoted, LisperExp::List(_)) && print_atom(quoted, readable).starts_with('@') {
+        return None;
+    }
+    Some((prefix, quoted))
+}
+
+// Prints an expression that isn't a list
+fn print_atom(exp: &LisperExp, readable: bool) -> String {
+    match exp {
+        LisperExp::Symbol(s) => s.to_string(),
+        LisperExp::Int(n) => n.to_string(),
+        LisperExp::BigInt(n) => n.to_string(),
+        LisperExp::Rational(n) => n.to_string(),
+        LisperExp::Number(n) => {
+            let printed = numbers::format_float(*n);
+            // Whole floats keep a decimal point, so they read back as floats
+            if printed.bytes().all(|b| b.is_ascii_digit() || b == b'-') {
+                format!("{}.0", printed)
+            } else {
+                printed
             }
+        },
+        LisperExp::Complex(n) => n.to_string(),
+        LisperExp::Bool(b) => b.to_string(),
+        LisperExp::Str(s) if readable => escape_string(s),
+        LisperExp::Str(s) => s.clone(),
+        LisperExp::List(_) => print_exp(exp, readable),
+        LisperExp::Func(func) => format!("#<function {}>", func.name),
+        LisperExp::Lambda(lambda) => match &lambda.name {
+            Some(name) => format!("#<lambda {}>", name),
+            None => "#<lambda>".to_string(),
+        },
+        LisperExp::Macro(mac) => match &mac.name {
+            Some(name) => format!("#<macro {}>", name),
+            None => "#<macro>".to_string(),
+        },
+        LisperExp::SyntaxRules(rules) => format!("#<macro {}>", rules.name),
+    }
+}
+
+// The different kinds of errors the interpreter can raise
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LisperErrKind {
+    ParseError,
+    SyntaxError,
+    UnboundSymbol,
+    TypeError,
+    ArityError,
+    IndexOutOfRange,
+    DivisionByZero,
+    IntegerOverflow,
+    DepthExceeded,
+    UserError
+}
+
+impl fmt::Display for LisperErrKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let str = match self {
+            LisperErrKind::ParseError => "Parse error",
+            LisperErrKind::SyntaxError => "Syntax error",
+            LisperErrKind::UnboundSymbol => "Unbound symbol",
+            LisperErrKind::TypeError => "Type error",
+            LisperErrKind::ArityError => "Arity error",
+            LisperErrKind::IndexOutOfRange => "Index out of range",
+            LisperErrKind::DivisionByZero => "Division by zero",
+            LisperErrKind::IntegerOverflow => "Integer overflow",
+            LisperErrKind::DepthExceeded => "Stack depth exceeded",
+            LisperErrKind::UserError => "Error",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+// A location in the source code. Offsets are in bytes, lines and columns
+// start at 1 and columns count chars.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LisperSpan {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+// An error type for the Lisp interperter
+#[derive(Clone, Debug)]
+pub struct LisperErr {
+    pub kind: LisperErrKind,
+    pub message: String,
+    // Where in the source the error happened, if known
+    pub span: Option<LisperSpan>,
+    // The Lisper functions that were running, innermost first
+    pub stack: Vec<String>
+}
+
+impl LisperErr {
+    pub fn new<S: Into<String>>(kind: LisperErrKind, message: S) -> LisperErr {
+        LisperErr {
+            kind,
+            message: message.into(),
+            span: None,
+            stack: vec![]
+        }
+    }
+
+    // Sets the span, unless a more precise one is already set
+    pub fn with_span(mut self, span: LisperSpan) -> LisperErr {
+        if self.span.is_none() {
+            self.span = Some(span);
         }
+        self
+    }
+
+    // Adds a function frame to the outside of the stack
+    pub fn with_frame(mut self, name: &str) -> LisperErr {
+        self.stack.push(name.to_string());
+        self
     }
-    return LisperExp::Number(sum)
 }
 
-fn modulus(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum %= n;
-                }
+impl error::Error for LisperErr {}
+
+impl fmt::Display for LisperErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)?;
+        if let Some(span) = &self.span {
+            write!(f, " (line {}, column {})", span.line, span.column)?;
+        }
+        // Deep recursion repeats the same frame, so runs are shown once
+        let mut frames = self.stack.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let mut count = 1;
+            while frames.peek() == Some(&frame) {
+                frames.next();
+                count += 1;
+            }
+            write!(f, "\n    in {}", frame)?;
+            if count > 1 {
+                write!(f, " ({} times)", count)?;
             }
         }
+        Ok(())
     }
-    return LisperExp::Number(sum)
 }
 
-fn less_than(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev < *n;
-                    prev = *n;
-                }
-            }
+// The default limit on how deeply evals can nest, such as in non-tail
+// recursion. A level of plain recursion takes under 1KB of native stack in
+// release builds and about 2KB in debug builds, calls that go through
+// builtins like map take more, so the stack is limited separately below.
+pub const DEFAULT_MAX_EVAL_DEPTH: usize = 1000;
+
+// The default limit on how many bytes of native stack nested evals can use,
+// deeper evaluation fails instead of overflowing the stack. It is enough for
+// the default depth of plain recursion in release builds and about 750 levels
+// in debug builds, and leaves a quarter of the 2MB stack a new thread gets
+// for the host.
+pub const DEFAULT_MAX_STACK_SIZE: usize = 1536 * 1024;
+
+// The default limit on how deeply lists can be nested in parsed source
+pub const DEFAULT_MAX_PARSE_DEPTH: usize = 256;
+
+// Settings and counters shared by a top level env and all its scopes
+#[derive(Debug)]
+struct LisperState {
+    max_eval_depth: Cell<usize>,
+    eval_depth: Cell<usize>,
+    max_stack_size: Cell<usize>,
+    // Where the native stack was when the outermost eval started
+    stack_start: Cell<usize>,
+    // The last mark given out to rename symbols in macro expansions
+    last_mark: Cell<usize>,
+    // The envs syntax-rules macros were defined in, by the mark of the macro
+    macro_envs: RefCell<HashMap<usize, LisperEnv>>
+}
+
+//  Represents the context where a Lisp expression executes. Each env is a
+//  scope with an optional parent scope, cloning an env shares its bindings.
+#[derive(Clone)]
+pub struct LisperEnv {
+    data: Rc<RefCell<HashMap<String, LisperExp>>>,
+    parent: Option<Rc<LisperEnv>>,
+    state: Rc<LisperState>
+}
+
+impl Default for LisperEnv {
+    fn default() -> LisperEnv {
+        LisperEnv {
+            data: Rc::new(RefCell::new(HashMap::new())),
+            parent: None,
+            state: Rc::new(LisperState {
+                max_eval_depth: Cell::new(DEFAULT_MAX_EVAL_DEPTH),
+                eval_depth: Cell::new(0),
+                max_stack_size: Cell::new(DEFAULT_MAX_STACK_SIZE),
+                stack_start: Cell::new(0),
+                last_mark: Cell::new(0),
+                macro_envs: RefCell::new(HashMap::new())
+            })
         }
     }
-    return LisperExp::Bool(res)
 }
 
-fn more_than(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev > *n;
-                    prev = *n;
-                }
-            }
+impl LisperEnv {
+    // Creates an empty top level scope
+    pub fn new() -> LisperEnv {
+        LisperEnv::default()
+    }
+
+    // Creates a new empty scope, with this env as its parent
+    pub fn child(&self) -> LisperEnv {
+        LisperEnv {
+            data: Rc::new(RefCell::new(HashMap::new())),
+            parent: Some(Rc::new(self.clone())),
+            state: self.state.clone()
+        }
+    }
+
+    // The limit on how deeply evals can nest, shared by all scopes of the env
+    pub fn max_eval_depth(&self) -> usize {
+        self.state.max_eval_depth.get()
+    }
+
+    pub fn set_max_eval_depth(&self, depth: usize) {
+        self.state.max_eval_depth.set(depth);
+    }
+
+    // The limit on how many bytes of native stack evals can use
+    pub fn max_stack_size(&self) -> usize {
+        self.state.max_stack_size.get()
+    }
+
+    pub fn set_max_stack_size(&self, size: usize) {
+        self.state.max_stack_size.set(size);
+    }
+
+    // Looks up a binding, walking outwards through the parent scopes
+    pub fn get(&self, name: &str) -> Option<LisperExp> {
+        self.lookup(name).or_else(|| {
+            let (original, env) = self.alias(name)?;
+            env.get(&original)
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Option<LisperExp> {
+        if let Some(value) = self.data.borrow().get(name) {
+            return Some(value.clone());
+        }
+        self.parent.as_ref().and_then(|parent| parent.lookup(name))
+    }
+
+    // The name and env a symbol renamed by a syntax-rules expansion refers to,
+    // when the expansion doesn't bind it. Renamed symbols are named
+    // name#macro.expansion, with the marks of the macro and the expansion.
+    fn alias(&self, name: &str) -> Option<(String, LisperEnv)> {
+        let (original, marks) = name.rsplit_once('#')?;
+        let (mac, _) = marks.split_once('.')?;
+        let env = self.state.macro_envs.borrow().get(&mac.parse().ok()?)?.clone();
+        Some((original.to_string(), env))
+    }
+
+    // Gives out a new mark, used to make renamed symbols unique
+    fn next_mark(&self) -> usize {
+        let mark = self.state.last_mark.get() + 1;
+        self.state.last_mark.set(mark);
+        mark
+    }
+
+    // Binds a value in this scope, shadowing any binding in the parent scopes
+    pub fn define(&self, name: &str, value: LisperExp) {
+        self.data.borrow_mut().insert(name.to_string(), value);
+    }
+
+    // Binds a builtin function implemented in Rust in this scope
+    pub fn register<F>(&self, name: &str, arity: LisperArity, doc: &str, func: F)
+    where F: Fn(&[LisperExp], &mut LisperEnv) -> Result<LisperExp, LisperErr> + 'static {
+        self.define(name, LisperExp::Func(Rc::new(LisperFunc::new(name, arity, doc, func))));
+    }
+
+    // Changes the value of the nearest existing binding
+    pub fn set(&self, name: &str, value: LisperExp) -> Result<(), LisperErr> {
+        match self.assign(name, value) {
+            Ok(()) => Ok(()),
+            Err(value) => match self.alias(name) {
+                Some((original, env)) => env.set(&original, value),
+                None => Err(LisperErr::new(LisperErrKind::UnboundSymbol, format!("cannot set! undefined symbol '{}'.", name))),
+            },
+        }
+    }
+
+    // Changes the nearest binding, or hands the value back if there is none
+    fn assign(&self, name: &str, value: LisperExp) -> Result<(), LisperExp> {
+        if let Some(binding) = self.data.borrow_mut().get_mut(name) {
+            *binding = value;
+            return Ok(());
+        }
+        match &self.parent {
+            Some(parent) => parent.assign(name, value),
+            None => Err(value),
         }
     }
-    return LisperExp::Bool(res)
 }
 
-fn equals(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev == *n;
-                    prev = *n;
-                }
-            }
+// Envs can contain themselves through closures, so only the names bound in
+// the current scope are shown
+impl fmt::Debug for LisperEnv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<String> = self.data.borrow().keys().cloned().collect();
+        names.sort();
+        f.debug_struct("LisperEnv")
+            .field("names", &names)
+            .field("has_parent", &self.parent.is_some())
+            .finish()
+    }
+}
+
+// A token read from the source, with where it was found
+#[derive(Clone, Debug, PartialEq)]
+pub struct LisperToken {
+    pub text: String,
+    pub span: LisperSpan
+}
+
+// An expression read from the source, where every sub expression keeps the
+// span it was read from
+#[derive(Clone, Debug)]
+pub enum LisperSyntax {
+    Atom(LisperExp, LisperSpan),
+    List(Vec<LisperSyntax>, LisperSpan)
+}
+
+impl LisperSyntax {
+    pub fn span(&self) -> LisperSpan {
+        match self {
+            LisperSyntax::Atom(_, span) | LisperSyntax::List(_, span) => *span,
+        }
+    }
+
+    // Gives the plain expression. Lists keep their spans, so errors raised
+    // when they are evaluated point at the source.
+    pub fn to_exp(&self) -> LisperExp {
+        match self {
+            LisperSyntax::Atom(exp, _) => exp.clone(),
+            LisperSyntax::List(items, span) => LisperExp::List(
+                LisperList::spanned(items.iter().map(|item| item.to_exp()).collect(), *span)
+            ),
+        }
+    }
+
+    // Gives the plain expression, without any spans
+    fn to_exp_without_spans(&self) -> LisperExp {
+        match self {
+            LisperSyntax::Atom(exp, _) => exp.clone(),
+            LisperSyntax::List(items, _) => LisperExp::List(items.iter().map(|item| item.to_exp_without_spans()).collect()),
         }
     }
-    return LisperExp::Bool(res)
 }
 
-fn less_or_equal(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev <= *n;
-                    println!("{} <= {} = {}", prev.to_string(), n.to_string(), res.to_string());
-                    prev = *n;
+// Reads through the source one char at a time, keeping track of the position
+struct LisperLexer<'a> {
+    source: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    line: usize,
+    column: usize
+}
+
+impl<'a> LisperLexer<'a> {
+    fn new(source: &'a str) -> LisperLexer<'a> {
+        LisperLexer {
+            source,
+            chars: source.char_indices().peekable(),
+            line: 1,
+            column: 1
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    // The byte offset of the next char
+    fn offset(&mut self) -> usize {
+        match self.chars.peek() {
+            Some((offset, _)) => *offset,
+            None => self.source.len(),
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn starts_with(&mut self, prefix: &str) -> bool {
+        let offset = self.offset();
+        self.source[offset..].starts_with(prefix)
+    }
+
+    // Skips a #| ... |# comment, which can contain nested block comments.
+    // Returns false if the source ends before the comment is closed.
+    fn skip_block_comment(&mut self) -> bool {
+        let mut depth = 0;
+        loop {
+            if self.starts_with("#|") {
+                depth += 1;
+                self.bump();
+                self.bump();
+            } else if self.starts_with("|#") {
+                depth -= 1;
+                self.bump();
+                self.bump();
+                if depth == 0 {
+                    return true;
                 }
+            } else if self.bump().is_none() {
+                return false;
             }
         }
     }
-    return LisperExp::Bool(res)
+
+    // A span starting at the next char, the end is filled in by finish
+    fn start(&mut self) -> LisperSpan {
+        let offset = self.offset();
+        LisperSpan { start: offset, end: offset, line: self.line, column: self.column }
+    }
+
+    fn finish(&mut self, text: String, mut span: LisperSpan) -> LisperToken {
+        span.end = self.offset();
+        LisperToken { text, span }
+    }
 }
 
-fn more_or_equal(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev >= *n;
-                    prev = *n;
+// Breaks an input string into tokens, recording where each token was found
+pub fn lex(source: &str) -> Vec<LisperToken> {
+    let mut lexer = LisperLexer::new(source);
+    let mut tokens: Vec<LisperToken> = vec![];
+
+    while let Some(c) = lexer.peek() {
+        if c.is_whitespace() {
+            lexer.bump();
+            continue;
+        }
+        if c == ';' {
+            // Line comments run to the end of the line
+            while lexer.peek().is_some_and(|c| c != '\n') {
+                lexer.bump();
+            }
+            continue;
+        }
+
+        let span = lexer.start();
+        let mut text = String::new();
+        if lexer.starts_with("#|") {
+            // An unterminated block comment is kept as a token, and fails in parse
+            if !lexer.skip_block_comment() {
+                tokens.push(lexer.finish("#|".to_string(), span));
+            }
+            continue;
+        } else if lexer.starts_with("#;") {
+            // Datum comments are left to parse, which skips the next expression
+            text.push_str("#;");
+            lexer.bump();
+            lexer.bump();
+        } else if c == '(' || c == ')' || c == '\'' || c == '`' {
+            text.push(c);
+            lexer.bump();
+        } else if c == ',' {
+            // Either unquote , or unquote-splicing ,@
+            text.push(c);
+            lexer.bump();
+            if lexer.peek() == Some('@') {
+                text.push('@');
+                lexer.bump();
+            }
+        } else if c == '"' {
+            // Strings run to the closing quote, keeping the escapes as they are.
+            // An unterminated string runs to the end, and fails in parse.
+            text.push(c);
+            lexer.bump();
+            while let Some(c) = lexer.bump() {
+                text.push(c);
+                if c == '\\' {
+                    if let Some(escaped) = lexer.bump() {
+                        text.push(escaped);
+                    }
+                } else if c == '"' {
+                    break;
+                }
+            }
+        } else {
+            while let Some(c) = lexer.peek() {
+                if c.is_whitespace() || "()\"'`,;".contains(c) {
+                    break;
                 }
+                text.push(c);
+                lexer.bump();
             }
         }
+        tokens.push(lexer.finish(text, span));
     }
-    return LisperExp::Bool(res)
+
+    tokens
 }
 
-fn sin(args: &LisperExp) -> LisperExp {
-    let mut res = 0.0;
-    if let LisperExp::List(list) = args {
-        if let LisperExp::Number(n) = list[0] {
-            res = n.sin();
-        }
-    }
-    LisperExp::Number(res)
+// Breaks an input string into tokens with lex and keeps just their text,
+// dropping the spans
+pub fn tokenize(expr: String) -> Vec<String> {
+    lex(&expr)
+        .into_iter()
+        .map(|token| token.text)
+        .collect()
 }
 
-fn cos(args: &LisperExp) -> LisperExp {
-    let mut res = 0.0;
-    if let LisperExp::List(list) = args {
-        if let LisperExp::Number(n) = list[0] {
-            res = n.cos();
-        }
+// Parses an array of string tokens and creates corresponding LisperExp objects
+pub fn parse(tokens: &[String]) -> Result<(LisperExp, &[String]), LisperErr> {
+    // Plain string tokens have no position, so parse them with empty spans
+    // and leave the spans out of any errors
+    let spanned: Vec<LisperToken> = tokens.iter()
+        .map(|text| LisperToken { text: text.clone(), span: LisperSpan::default() })
+        .collect();
+
+    let (syntax, rest) = parse_spanned(&spanned).map_err(|mut err| {
+        err.span = None;
+        err
+    })?;
+
+    Ok((syntax.to_exp_without_spans(), &tokens[tokens.len() - rest.len()..]))
+}
+
+// Parses tokens from lex into a syntax tree that keeps the source spans
+pub fn parse_spanned(tokens: &[LisperToken]) -> Result<(LisperSyntax, &[LisperToken]), LisperErr> {
+    parse_spanned_with_limit(tokens, DEFAULT_MAX_PARSE_DEPTH)
+}
+
+// Like parse_spanned, with a limit on how deeply lists can be nested
+pub fn parse_spanned_with_limit(tokens: &[LisperToken], max_depth: usize) -> Result<(LisperSyntax, &[LisperToken]), LisperErr> {
+    parse_nested(tokens, 0, max_depth)
+}
+
+// Parses all the top level expressions in the tokens, in order
+pub fn parse_program(tokens: &[LisperToken]) -> Result<Vec<LisperSyntax>, LisperErr> {
+    let mut forms: Vec<LisperSyntax> = vec![];
+    let mut rest = skip_datum_comments(tokens, 0, DEFAULT_MAX_PARSE_DEPTH)?;
+    while !rest.is_empty() {
+        let (form, more) = parse_spanned(rest)?;
+        forms.push(form);
+        rest = skip_datum_comments(more, 0, DEFAULT_MAX_PARSE_DEPTH)?;
+    }
+    Ok(forms)
+}
+
+// Reads all the top level expressions in the source
+pub fn read_all(source: &str) -> Result<Vec<LisperExp>, LisperErr> {
+    let tokens = lex(source);
+    Ok(parse_program(&tokens)?.iter().map(LisperSyntax::to_exp).collect())
+}
+
+// Skips the #; datum comments at the start of the tokens, each along with
+// the expression after it
+fn skip_datum_comments(tokens: &[LisperToken], depth: usize, max_depth: usize) -> Result<&[LisperToken], LisperErr> {
+    let mut rest = tokens;
+    while let Some((first, more)) = rest.split_first() {
+        if first.text != "#;" {
+            break;
+        }
+        if more.is_empty() {
+            return Err(LisperErr::new(LisperErrKind::ParseError, "expected an expression after #;.").with_span(first.span));
+        }
+        let (_, after) = parse_nested(more, depth, max_depth)?;
+        rest = after;
+    }
+    Ok(rest)
+}
+
+// Parses one expression, depth is the number of lists it is nested in
+fn parse_nested(tokens: &[LisperToken], depth: usize, max_depth: usize) -> Result<(LisperSyntax, &[LisperToken]), LisperErr> {
+    let tokens = skip_datum_comments(tokens, depth, max_depth)?;
+    let (first, rest) = tokens.split_first()
+        .ok_or(
+            LisperErr::new(LisperErrKind::ParseError, "unexpected end of input.")
+        )?;
+
+    let opens_list = ["(", "'", "`", ",", ",@"].contains(&first.text.as_str());
+    if opens_list && depth >= max_depth {
+        return Err(LisperErr::new(
+            LisperErrKind::DepthExceeded,
+            format!("parse reached the maximum nesting depth of {}.", max_depth)
+        ).with_span(first.span));
+    }
+
+    let mut parsed_result: Vec<LisperSyntax> = vec![];
+
+    match first.text.as_str() {
+        "(" => {
+            let mut more = rest;
+            loop {
+                more = skip_datum_comments(more, depth + 1, max_depth)?;
+                // Point at the ( that was never closed
+                let (next, more_next) = more.split_first()
+                    .ok_or(
+                        LisperErr::new(LisperErrKind::ParseError, "unbalanced (, missing ).").with_span(first.span)
+                    )?;
+                if next.text == ")" {
+                    let span = LisperSpan { end: next.span.end, ..first.span };
+                    return Ok((LisperSyntax::List(parsed_result, span), more_next))
+                }
+                let (syntax, new_more) = parse_nested(more, depth + 1, max_depth)?;
+                parsed_result.push(syntax);
+                more = new_more;
+            }
+        },
+        ")" => {
+            Err(LisperErr::new(LisperErrKind::ParseError, "found unexpected ).").with_span(first.span))
+        },
+        "'" | "`" | "," | ",@" => {
+            // Reader shorthand, 'x reads as (quote x) and so on
+            let name = match first.text.as_str() {
+                "'" => "quote",
+                "`" => "quasiquote",
+                "," => "unquote",
+                _ => "unquote-splicing",
+            };
+            if rest.is_empty() {
+                return Err(LisperErr::new(
+                    LisperErrKind::ParseError,
+                    format!("expected an expression after {}.", first.text)
+                ).with_span(first.span));
+            }
+            let (quoted, more) = parse_nested(rest, depth + 1, max_depth)?;
+            let span = LisperSpan { end: quoted.span().end, ..first.span };
+            let symbol = LisperSyntax::Atom(LisperExp::Symbol(name.to_string()), first.span);
+            Ok((LisperSyntax::List(vec![symbol, quoted], span), more))
+        },
+        _ => {
+            let parsed_token:LisperExp = parse_token(&first.text).map_err(|err| err.with_span(first.span))?;
+            Ok((LisperSyntax::Atom(parsed_token, first.span), rest))
+        }
+    }
+}
+
+// Parses an individual token and creates either a Bool, number, Str or Symbol LisperExp
+fn parse_token(token: &str) -> Result<LisperExp, LisperErr> {
+    if token == "#|" {
+        Err(LisperErr::new(LisperErrKind::ParseError, "unterminated block comment, missing |#."))
+    } else if token.starts_with('"') {
+        Ok(LisperExp::Str(unescape_string(token)?))
+    } else if let Result::Ok(parsed_bool) = token.parse::<bool>() {
+        Ok(LisperExp::Bool(parsed_bool))
+    } else if let Some(parsed_number) = numbers::parse_number(token) {
+        parsed_number
+    } else if token.contains('#') {
+        // Symbols made by gensym and syntax-rules have a #, so source can't
+        // name them by accident
+        Err(LisperErr::new(
+            LisperErrKind::ParseError,
+            format!("symbol {} cannot contain #, it is reserved for generated symbols.", token)
+        ))
+    } else {
+        Ok(LisperExp::Symbol(token.to_string()))
+    }
+}
+
+// Turns a string literal token, including the quotes, into the string it
+// represents by replacing the escape sequences
+fn unescape_string(token: &str) -> Result<String, LisperErr> {
+    let inner = match token.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        Some(inner) => inner,
+        _ => return Err(LisperErr::new(LisperErrKind::ParseError, "unterminated string, missing \".")),
+    };
+
+    let mut res = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('r') => res.push('\r'),
+            Some('"') => res.push('"'),
+            Some('\\') => res.push('\\'),
+            Some('u') => {
+                // \u{...} with the hex code of a unicode scalar value
+                let escape_err = || LisperErr::new(LisperErrKind::ParseError, "invalid \\u{...} escape in string.");
+                if chars.next() != Some('{') {
+                    return Err(escape_err());
+                }
+                let mut hex = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => hex.push(c),
+                        None => return Err(escape_err()),
+                    }
+                }
+                let code = u32::from_str_radix(&hex, 16).map_err(|_| escape_err())?;
+                res.push(std::char::from_u32(code).ok_or_else(escape_err)?);
+            },
+            Some(other) => return Err(
+                LisperErr::new(LisperErrKind::ParseError, format!("unknown escape \\{} in string.", other))
+            ),
+            None => return Err(LisperErr::new(LisperErrKind::ParseError, "unterminated string, missing \".")),
+        }
+    }
+
+    Ok(res)
+}
+
+// Writes a string as a literal that reads back as the same string
+fn escape_string(string: &str) -> String {
+    let mut res = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            c if c.is_control() => res.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+// Create a default environment containing fundamental functions
+pub fn create_default_env() -> LisperEnv {
+    let env = LisperEnv::new();
+
+    // Basic math functions
+    env.register("+", LisperArity::AtLeast(0), "Adds the numbers together.", add);
+    env.register("add", LisperArity::AtLeast(0), "Adds the numbers together.", add);
+    env.register("-", LisperArity::AtLeast(1), "Subtracts the rest of the numbers from the first, or negates a single number.", sub);
+    env.register("sub", LisperArity::AtLeast(1), "Subtracts the rest of the numbers from the first, or negates a single number.", sub);
+    env.register("*", LisperArity::AtLeast(1), "Multiplies the numbers together.", mul);
+    env.register("mul", LisperArity::AtLeast(1), "Multiplies the numbers together.", mul);
+    env.register("/", LisperArity::AtLeast(1), "Divides the first number by the rest of the numbers.", div);
+    env.register("div", LisperArity::AtLeast(1), "Divides the first number by the rest of the numbers.", div);
+    env.register("%", LisperArity::AtLeast(1), "The remainder of dividing the first number by the rest.", modulus);
+    env.register("mod", LisperArity::AtLeast(1), "The remainder of dividing the first number by the rest.", modulus);
+
+    // Comparators
+    env.register("<", LisperArity::AtLeast(1), "True if the numbers are increasing.", less_than);
+    env.register(">", LisperArity::AtLeast(1), "True if the numbers are decreasing.", more_than);
+    env.register("=", LisperArity::AtLeast(1), "True if the values are all equal, numbers are compared by value.", numeric_equals);
+    env.register("==", LisperArity::AtLeast(1), "True if the values are all equal, numbers are compared by value.", numeric_equals);
+    env.register("<=", LisperArity::AtLeast(1), "True if the numbers are non-decreasing.", less_or_equal);
+    env.register(">=", LisperArity::AtLeast(1), "True if the numbers are non-increasing.", more_or_equal);
+
+    // Equality of any values
+    env.register("eq?", LisperArity::Exact(2), "True if the two values are equal.", equals);
+    env.register("equal?", LisperArity::Exact(2), "True if the two values are equal.", equals);
+
+    // Trig functions
+    env.register("sin", LisperArity::Exact(1), "The sine of a number in radians.", sin);
+    env.register("cos", LisperArity::Exact(1), "The cosine of a number in radians.", cos);
+    env.register("tan", LisperArity::Exact(1), "The tangent of a number in radians.", tan);
+
+    // Lists
+    lists::register(&env);
+
+    // Strings
+    strings::register(&env);
+    macros::register(&env);
+    math::register(&env);
+    numbers::register(&env);
+
+    // Errors
+    env.register("error", LisperArity::AtLeast(1), "Raises an error with the arguments as message.", error);
+
+    // Constants
+    env.define("pi", LisperExp::Number(core::f64::consts::PI));
+
+    env
+}
+
+// The outcome of one step of eval. Expressions in tail position are handed
+// back to the loop in eval, instead of being evaluated recursively, so tail
+// calls run in constant stack space.
+enum LisperTail {
+    // The final value
+    Done(LisperExp),
+    // Continue by evaluating the expression in the env
+    Eval(LisperExp, LisperEnv),
+    // Continue with the last expression of a function body, the name of the
+    // function is kept for error stacks
+    Call(String, LisperExp, LisperEnv)
+}
+
+// Evaluates the expressions of a program in order, and returns the value of
+// the last one. An empty program evaluates to the empty list.
+pub fn eval_program(forms: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    eval_body(forms, env)
+}
+
+// Counts one level of eval depth for as long as it is alive
+struct LisperDepthGuard {
+    state: Rc<LisperState>
+}
+
+impl LisperDepthGuard {
+    fn enter(state: &Rc<LisperState>) -> Result<LisperDepthGuard, Box<LisperErr>> {
+        let depth = state.eval_depth.get();
+        if depth >= state.max_eval_depth.get() {
+            return Err(Box::new(LisperErr::new(
+                LisperErrKind::DepthExceeded,
+                format!("eval reached the maximum depth of {}.", depth)
+            )));
+        }
+        let position = stack_position();
+        if depth == 0 {
+            state.stack_start.set(position);
+        }
+        if position.abs_diff(state.stack_start.get()) > state.max_stack_size.get() {
+            return Err(Box::new(LisperErr::new(
+                LisperErrKind::DepthExceeded,
+                format!("eval ran out of stack space at depth {}.", depth)
+            )));
+        }
+        state.eval_depth.set(depth + 1);
+        Ok(LisperDepthGuard { state: state.clone() })
+    }
+}
+
+// The address of a local, which moves as the native stack grows
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+impl Drop for LisperDepthGuard {
+    fn drop(&mut self) {
+        self.state.eval_depth.set(self.state.eval_depth.get() - 1);
+    }
+}
+
+// Evaluates a given Lisp expression, and returns a new one with the result.
+pub fn eval(exp: LisperExp, env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    eval_nested(exp, env).map_err(|err| *err)
+}
+
+// Eval_nested, eval_step, eval_call and eval_args are on the native stack
+// once for every level of nested evaluation, so they are kept small. Special
+// forms run in functions of their own, and errors are boxed on the way out.
+fn eval_nested(exp: LisperExp, env: &mut LisperEnv) -> Result<LisperExp, Box<LisperErr>> {
+    let _depth = LisperDepthGuard::enter(&env.state)?;
+
+    let mut exp = exp;
+    let mut env = env.clone();
+    // The function whose body is being evaluated, tail calls replace it
+    let mut frame: Option<String> = None;
+
+    loop {
+        match eval_step(exp, &mut env) {
+            Ok(LisperTail::Done(value)) => return Ok(value),
+            Ok(LisperTail::Eval(next_exp, next_env)) => {
+                exp = next_exp;
+                env = next_env;
+            },
+            Ok(LisperTail::Call(name, next_exp, next_env)) => {
+                frame = Some(name);
+                exp = next_exp;
+                env = next_env;
+            },
+            Err(mut err) => {
+                if let Some(name) = frame {
+                    err.stack.push(name);
+                }
+                return Err(err);
+            },
+        }
+    }
+}
+
+// The names handled by eval_step as special forms, plus the keywords used
+// inside them. Macro expansions never rename these.
+const SPECIAL_FORMS: &[&str] = &[
+    "def", "quote", "quasiquote", "unquote", "unquote-splicing", "fn", "lambda", "defn", "defmacro",
+    "define-syntax", "syntax-rules", "set!", "let", "let*", "letrec", "begin", "do", "if", "cond", "when", "unless", "else", "&"
+];
+
+// Evaluates a special form, which gets its arguments unevaluated
+type LisperSpecialForm = fn(&[LisperExp], &mut LisperEnv) -> Result<LisperTail, LisperErr>;
+
+// The function that evaluates the special form with the name, if it is one
+fn special_form(sym: &str) -> Option<LisperSpecialForm> {
+    let form: LisperSpecialForm = match sym {
+        "def" => |args, env| eval_def(args, env).map(LisperTail::Done),
+        "quote" => |args, _| eval_quote(args).map(LisperTail::Done),
+        "quasiquote" => |args, env| eval_quasiquote(args, env).map(LisperTail::Done),
+        "unquote" => |_, _| Err(
+            LisperErr::new(LisperErrKind::SyntaxError, "unquote is only allowed inside quasiquote.")
+        ),
+        "unquote-splicing" => |_, _| Err(
+            LisperErr::new(LisperErrKind::SyntaxError, "unquote-splicing is only allowed inside quasiquote.")
+        ),
+        "fn" | "lambda" => |args, env| eval_fn(args, env).map(LisperTail::Done),
+        "defn" => |args, env| eval_defn(args, env).map(LisperTail::Done),
+        "defmacro" => |args, env| eval_defmacro(args, env).map(LisperTail::Done),
+        "define-syntax" => |args, env| syntax_rules::eval_define_syntax(args, env).map(LisperTail::Done),
+        "syntax-rules" => |_, _| Err(
+            LisperErr::new(LisperErrKind::SyntaxError, "syntax-rules is only allowed inside define-syntax.")
+        ),
+        "set!" => |args, env| eval_set(args, env).map(LisperTail::Done),
+        "let" => eval_let,
+        "let*" => eval_let_star,
+        "letrec" => eval_letrec,
+        // Evaluates the expressions in order, the last one is in tail position
+        "begin" | "do" => eval_body_tail,
+        "if" => eval_if,
+        "cond" => eval_cond,
+        "when" => |args, env| eval_when(args, env, true),
+        "unless" => |args, env| eval_when(args, env, false),
+        _ => return None,
+    };
+    Some(form)
+}
+
+// Evaluates an expression up to the point where it has a value, or has an
+// expression in tail position left to evaluate
+fn eval_step(exp: LisperExp, env: &mut LisperEnv) -> Result<LisperTail, Box<LisperErr>> {
+    match &exp {
+        LisperExp::List(list) => eval_list(list, env),
+        // Look up the value bound to the symbol
+        LisperExp::Symbol(sym) => lookup(sym, env),
+        // Everything else evaluates to itself
+        LisperExp::Int(_) | LisperExp::BigInt(_) | LisperExp::Rational(_) | LisperExp::Number(_) | LisperExp::Complex(_) | LisperExp::Bool(_) | LisperExp::Str(_) | LisperExp::Func(_) | LisperExp::Lambda(_) | LisperExp::Macro(_) | LisperExp::SyntaxRules(_) => Ok(LisperTail::Done(exp)),
+    }
+}
+
+// Evaluates a special form or a call. Errors from a list read from the
+// source point at it, unless they came from a more precise place.
+fn eval_list(list: &LisperList, env: &mut LisperEnv) -> Result<LisperTail, Box<LisperErr>> {
+    // Split the symbol from the arguments
+    let Some((first, args)) = list.split_first() else {
+        return Err(empty_list_error());
+    };
+
+    let form = match first {
+        LisperExp::Symbol(sym) => special_form(sym),
+        _ => None,
+    };
+    let res = match form {
+        Some(form) => eval_special(form, args, env),
+        None => eval_call(first, args, env),
+    };
+    res.map_err(|err| in_span(err, list))
+}
+
+// Sets the span of an error to the list it came from, if it has none
+fn in_span(mut err: Box<LisperErr>, list: &LisperList) -> Box<LisperErr> {
+    if err.span.is_none() {
+        err.span = list.span();
+    }
+    err
+}
+
+// The error for evaluating (), built outside eval_list to keep its frame small
+fn empty_list_error() -> Box<LisperErr> {
+    Box::new(LisperErr::new(LisperErrKind::SyntaxError, "cannot evaluate an empty list."))
+}
+
+// Evaluates a special form with its unevaluated arguments
+fn eval_special(form: LisperSpecialForm, args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, Box<LisperErr>> {
+    form(args, env).map_err(Box::new)
+}
+
+// The value bound to a symbol, or an error if it isn't bound
+fn lookup(sym: &str, env: &LisperEnv) -> Result<LisperTail, Box<LisperErr>> {
+    match env.get(sym) {
+        Some(value) => Ok(LisperTail::Done(value)),
+        None => Err(Box::new(
+            LisperErr::new(LisperErrKind::UnboundSymbol, format!("symbol '{}' not found.", sym))
+        )),
+    }
+}
+
+// Evaluates a call of a function or a macro, first is the expression for the
+// function and args the unevaluated arguments
+fn eval_call(first: &LisperExp, args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, Box<LisperErr>> {
+    // Get the function by evaluating the first item
+    let lisper_func = eval_nested(first.clone(), env)?;
+
+    // Macros get the arguments unevaluated, and the expression they
+    // return is evaluated in place of the call
+    if let LisperExp::Macro(_) | LisperExp::SyntaxRules(_) = &lisper_func {
+        return expand_call(&lisper_func, args, env);
+    }
+
+    let evaluated_args = eval_args(args, env)?;
+    call_value(&lisper_func, evaluated_args, env)
+}
+
+// Evaluates each argument of a call, in order
+fn eval_args(args: &[LisperExp], env: &mut LisperEnv) -> Result<Vec<LisperExp>, Box<LisperErr>> {
+    let mut evaluated_args: Vec<LisperExp> = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        evaluated_args.push(eval_nested(arg.clone(), env)?);
+    }
+    Ok(evaluated_args)
+}
+
+// Expands a call of a macro, leaving the expansion to be evaluated in place
+// of the call
+fn expand_call(mac: &LisperExp, args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, Box<LisperErr>> {
+    let expansion = match mac {
+        LisperExp::SyntaxRules(rules) => syntax_rules::expand(rules, args),
+        LisperExp::Macro(mac) => expand_macro(mac, args),
+        other => Err(
+            LisperErr::new(LisperErrKind::TypeError, format!("{} is not a macro.", other))
+        ),
+    };
+    match expansion {
+        Ok(expansion) => Ok(LisperTail::Eval(expansion, env.clone())),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+// Calls a function value with evaluated arguments, leaving the body of
+// lambdas in tail position
+fn call_value(func: &LisperExp, args: Vec<LisperExp>, env: &mut LisperEnv) -> Result<LisperTail, Box<LisperErr>> {
+    let res = match func {
+        LisperExp::Lambda(lambda) => call_tail(lambda, args),
+        other => apply(other, args, env).map(LisperTail::Done),
+    };
+    res.map_err(Box::new)
+}
+
+// Calls a function value with already evaluated arguments
+pub fn apply(func: &LisperExp, args: Vec<LisperExp>, env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match func {
+        LisperExp::Func(func) => func.call(&args, env),
+        LisperExp::Lambda(lambda) => {
+            let mut local_env = bind_args(lambda, args)?;
+            let name = lambda.name.as_deref().unwrap_or("lambda");
+            eval_body(&lambda.body, &mut local_env).map_err(|err| err.with_frame(name))
+        },
+        other => Err(
+            LisperErr::new(LisperErrKind::TypeError, format!("{} is not a function.", other))
+        ),
+    }
+}
+
+// Binds the arguments in a new scope on top of the env the function was
+// defined in
+fn bind_args(lambda: &LisperLambda, args: Vec<LisperExp>) -> Result<LisperEnv, LisperErr> {
+    let arity = match lambda.rest {
+        Some(_) => LisperArity::AtLeast(lambda.params.len()),
+        None => LisperArity::Exact(lambda.params.len()),
+    };
+    if !arity.accepts(args.len()) {
+        return Err(LisperErr::new(
+            LisperErrKind::ArityError,
+            format!(
+                "{} expects {} arguments, got {}.",
+                lambda.name.as_deref().unwrap_or("lambda"), arity, args.len()
+            )
+        ));
+    }
+
+    let local_env = lambda.env.child();
+    let mut args = args.into_iter();
+    for (name, value) in lambda.params.iter().zip(args.by_ref()) {
+        local_env.define(name, value);
+    }
+    if let Some(rest) = &lambda.rest {
+        local_env.define(rest, LisperExp::List(args.collect()));
+    }
+    Ok(local_env)
+}
+
+// Calls a lambda, leaving the last expression of the body in tail position
+fn call_tail(lambda: &LisperLambda, args: Vec<LisperExp>) -> Result<LisperTail, LisperErr> {
+    let name = lambda.name.clone().unwrap_or_else(|| "lambda".to_string());
+    let mut local_env = bind_args(lambda, args)?;
+    match eval_body_tail(&lambda.body, &mut local_env).map_err(|err| err.with_frame(&name))? {
+        LisperTail::Eval(last, last_env) => Ok(LisperTail::Call(name, last, last_env)),
+        step => Ok(step),
+    }
+}
+
+// Calls a macro with the unevaluated arguments, returning its expansion
+fn expand_macro(mac: &LisperLambda, args: &[LisperExp]) -> Result<LisperExp, LisperErr> {
+    let mut local_env = bind_args(mac, args.to_vec())?;
+    let name = mac.name.as_deref().unwrap_or("macro");
+    eval_body(&mac.body, &mut local_env).map_err(|err| err.with_frame(name))
+}
+
+// Evaluates (def name expr), binding the value of expr to name in the env
+fn eval_def(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    if args.len() != 2 {
+        return Err(LisperErr::new(LisperErrKind::SyntaxError, "def expects a name and a value."));
+    }
+
+    let name = match &args[0] {
+        LisperExp::Symbol(name) => name.clone(),
+        other => return Err(
+            LisperErr::new(LisperErrKind::SyntaxError, format!("def expects a symbol as name, got {}.", other))
+        ),
+    };
+
+    let mut value = eval(args[1].clone(), env)?;
+    // Anonymous functions take the name they are first bound to
+    if let LisperExp::Lambda(lambda) = &mut value {
+        if lambda.name.is_none() {
+            Rc::make_mut(lambda).name = Some(name.clone());
+        }
+    }
+    env.define(&name, value);
+
+    Ok(LisperExp::Symbol(name))
+}
+
+// Evaluates (quote x), returning x without evaluating it
+fn eval_quote(args: &[LisperExp]) -> Result<LisperExp, LisperErr> {
+    match args {
+        [quoted] => Ok(quoted.clone()),
+        _ => Err(LisperErr::new(LisperErrKind::SyntaxError, "quote expects one expression.")),
+    }
+}
+
+// Evaluates (quasiquote template), which works like quote except for the
+// (unquote x) and (unquote-splicing x) parts, which are evaluated
+fn eval_quasiquote(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match args {
+        [template] => quasiquote(template, 1, env),
+        _ => Err(LisperErr::new(LisperErrKind::SyntaxError, "quasiquote expects one expression.")),
+    }
+}
+
+// Returns the argument of a (name x) form, such as (unquote x)
+fn quote_form<'a>(exp: &'a LisperExp, name: &str) -> Option<&'a LisperExp> {
+    match exp {
+        LisperExp::List(items) if items.len() == 2 => match &items[0] {
+            LisperExp::Symbol(sym) if sym == name => Some(&items[1]),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Expands a quasiquote template. Depth counts the nested quasiquotes, only
+// unquotes that belong to the outermost quasiquote are evaluated. Each level
+// of the template counts towards the eval depth, as templates built at run
+// time can be nested arbitrarily deep.
+fn quasiquote(template: &LisperExp, depth: usize, env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let _depth = LisperDepthGuard::enter(&env.state).map_err(|err| *err)?;
+    let wrap = |name: &str, exp: LisperExp| LisperExp::List(vec![LisperExp::Symbol(name.to_string()), exp].into());
+
+    if let Some(exp) = quote_form(template, "unquote") {
+        return if depth == 1 {
+            eval(exp.clone(), env)
+        } else {
+            Ok(wrap("unquote", quasiquote(exp, depth - 1, env)?))
+        };
+    }
+    if let Some(exp) = quote_form(template, "quasiquote") {
+        return Ok(wrap("quasiquote", quasiquote(exp, depth + 1, env)?));
+    }
+    if quote_form(template, "unquote-splicing").is_some() && depth == 1 {
+        return Err(LisperErr::new(LisperErrKind::SyntaxError, "unquote-splicing is only allowed inside a list."));
+    }
+
+    let items = match template {
+        LisperExp::List(items) => items,
+        other => return Ok(other.clone()),
+    };
+
+    let mut res: Vec<LisperExp> = vec![];
+    for item in items.iter() {
+        match quote_form(item, "unquote-splicing") {
+            // Splice the items of the evaluated list into the result
+            Some(exp) if depth == 1 => match &eval(exp.clone(), env)? {
+                LisperExp::List(spliced) => res.extend(spliced.iter().cloned()),
+                other => return Err(LisperErr::new(
+                    LisperErrKind::TypeError,
+                    format!("unquote-splicing expects a list, got {} {}.", other.type_name(), other)
+                )),
+            },
+            Some(exp) => res.push(wrap("unquote-splicing", quasiquote(exp, depth - 1, env)?)),
+            None => res.push(quasiquote(item, depth, env)?),
+        }
+    }
+
+    Ok(LisperExp::List(res.into()))
+}
+
+// Evaluates (set! name expr), changing the value of the nearest binding of name
+fn eval_set(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    if args.len() != 2 {
+        return Err(LisperErr::new(LisperErrKind::SyntaxError, "set! expects a name and a value."));
+    }
+
+    let name = match &args[0] {
+        LisperExp::Symbol(name) => name.clone(),
+        other => return Err(
+            LisperErr::new(LisperErrKind::SyntaxError, format!("set! expects a symbol as name, got {}.", other))
+        ),
+    };
+
+    let value = eval(args[1].clone(), env)?;
+    env.set(&name, value.clone())?;
+
+    Ok(value)
+}
+
+// Evaluates (let ((name expr)...) body...). The values are evaluated in the
+// current scope, then bound in a new child scope that the body runs in.
+fn eval_let(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, LisperErr> {
+    if let Some((LisperExp::Symbol(name), rest)) = args.split_first() {
+        return eval_named_let(name, rest, env);
+    }
+
+    let (bindings, body) = let_bindings("let", args)?;
+    let mut local_env = env.child();
+    for (name, exp) in bindings {
+        local_env.define(name, eval(exp.clone(), env)?);
+    }
+
+    eval_body_tail(body, &mut local_env)
+}
+
+// Evaluates (let* ((name expr)...) body...), where each value can refer to
+// the names bound before it
+fn eval_let_star(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, LisperErr> {
+    let (bindings, body) = let_bindings("let*", args)?;
+    let mut local_env = env.child();
+    for (name, exp) in bindings {
+        let value = eval(exp.clone(), &mut local_env)?;
+        // Each binding gets its own scope, like nested lets
+        local_env = local_env.child();
+        local_env.define(name, value);
+    }
+
+    eval_body_tail(body, &mut local_env)
+}
+
+// Evaluates (letrec ((name expr)...) body...). The values are evaluated in
+// order in the new scope, so functions bound there can refer to each other.
+fn eval_letrec(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, LisperErr> {
+    let (bindings, body) = let_bindings("letrec", args)?;
+    let mut local_env = env.child();
+    for (name, exp) in bindings {
+        let value = eval(exp.clone(), &mut local_env)?;
+        local_env.define(name, value);
+    }
+
+    eval_body_tail(body, &mut local_env)
+}
+
+// Evaluates (let name ((param expr)...) body...), which binds name to a
+// function of the params and calls it with the values. Calling name in tail
+// position loops.
+fn eval_named_let(name: &str, args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, LisperErr> {
+    let (bindings, body) = let_bindings("let", args)?;
+
+    let mut params: Vec<String> = vec![];
+    let mut values: Vec<LisperExp> = vec![];
+    for (param, exp) in bindings {
+        params.push(param.to_string());
+        values.push(eval(exp.clone(), env)?);
+    }
+
+    // The function is only visible inside its own body
+    let loop_env = env.child();
+    let lambda = Rc::new(LisperLambda {
+        name: Some(name.to_string()),
+        params: Rc::new(params),
+        rest: None,
+        body: Rc::new(body.to_vec()),
+        env: loop_env.clone()
+    });
+    loop_env.define(name, LisperExp::Lambda(lambda.clone()));
+
+    call_tail(&lambda, values)
+}
+
+// The (name expr) pairs of a let form
+type LetBindings<'a> = Vec<(&'a str, &'a LisperExp)>;
+
+// Splits the args of a let form into its bindings and its body
+fn let_bindings<'a>(form: &str, args: &'a [LisperExp]) -> Result<(LetBindings<'a>, &'a [LisperExp]), LisperErr> {
+    let (bindings, body) = match args.split_first() {
+        Some((LisperExp::List(bindings), body)) => (bindings, body),
+        _ => return Err(LisperErr::new(LisperErrKind::SyntaxError, format!("{} expects a list of bindings.", form))),
+    };
+
+    let mut pairs: LetBindings = vec![];
+    for binding in bindings.iter() {
+        match binding {
+            LisperExp::List(pair) if pair.len() == 2 => match &pair[0] {
+                LisperExp::Symbol(name) => pairs.push((name, &pair[1])),
+                other => return Err(
+                    LisperErr::new(LisperErrKind::SyntaxError, format!("{} expects a symbol as name, got {}.", form, other))
+                ),
+            },
+            other => return Err(
+                LisperErr::new(LisperErrKind::SyntaxError, format!("{} expects (name value) bindings, got {}.", form, other))
+            ),
+        }
+    }
+
+    Ok((pairs, body))
+}
+
+// Evaluates (fn (params...) body...), creating a function that captures the
+// current environment
+fn eval_fn(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    make_lambda("fn", args, env).map(|lambda| LisperExp::Lambda(Rc::new(lambda)))
+}
+
+// Creates a lambda from ((params...) body...), form is the name used in errors.
+// A parameter after & takes the rest of the arguments as a list.
+fn make_lambda(form: &str, args: &[LisperExp], env: &LisperEnv) -> Result<LisperLambda, LisperErr> {
+    let (params, body) = match args.split_first() {
+        Some((LisperExp::List(params), body)) => (params, body),
+        _ => return Err(LisperErr::new(LisperErrKind::SyntaxError, format!("{} expects a list of parameters.", form))),
+    };
+
+    let mut param_names: Vec<String> = vec![];
+    let mut rest: Option<String> = None;
+    let mut params = params.iter();
+    while let Some(param) = params.next() {
+        match param {
+            LisperExp::Symbol(name) if name == "&" => match (params.next(), params.next()) {
+                (Some(LisperExp::Symbol(name)), None) => rest = Some(name.clone()),
+                _ => return Err(
+                    LisperErr::new(LisperErrKind::SyntaxError, format!("{} expects one parameter after &.", form))
+                ),
+            },
+            LisperExp::Symbol(name) => param_names.push(name.clone()),
+            other => return Err(
+                LisperErr::new(LisperErrKind::SyntaxError, format!("{} parameters must be symbols, got {}.", form, other))
+            ),
+        }
+    }
+
+    Ok(LisperLambda {
+        name: None,
+        params: Rc::new(param_names),
+        rest,
+        body: Rc::new(body.to_vec()),
+        env: env.clone()
+    })
+}
+
+// Evaluates (defn name (params...) body...), shorthand for (def name (fn ...))
+fn eval_defn(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let (name, rest) = args.split_first()
+    .ok_or(
+        LisperErr::new(LisperErrKind::SyntaxError, "defn expects a name, parameters and a body.")
+    )?;
+
+    let lambda = eval_fn(rest, env)?;
+    eval_def(&[name.clone(), lambda], env)
+}
+
+// Evaluates (defmacro name (params...) body...), binding a macro to name. The
+// body gets the arguments of a call unevaluated and returns the expression to
+// evaluate instead.
+fn eval_defmacro(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let (name, rest) = match args.split_first() {
+        Some((LisperExp::Symbol(name), rest)) => (name, rest),
+        _ => return Err(LisperErr::new(LisperErrKind::SyntaxError, "defmacro expects a name, parameters and a body.")),
+    };
+
+    let mut mac = make_lambda("defmacro", rest, env)?;
+    mac.name = Some(name.clone());
+    env.define(name, LisperExp::Macro(Rc::new(mac)));
+
+    Ok(LisperExp::Symbol(name.clone()))
+}
+
+// Decides whether a value counts as true in a conditional. Only false and the
+// empty list are falsy, every other value is truthy (including 0).
+pub fn is_truthy(exp: &LisperExp) -> bool {
+    match exp {
+        LisperExp::Bool(b) => *b,
+        LisperExp::List(list) => !list.is_empty(),
+        LisperExp::Symbol(_) | LisperExp::Int(_) | LisperExp::BigInt(_) | LisperExp::Rational(_) | LisperExp::Number(_) | LisperExp::Complex(_) | LisperExp::Str(_) | LisperExp::Func(_) | LisperExp::Lambda(_) | LisperExp::Macro(_) | LisperExp::SyntaxRules(_) => true,
+    }
+}
+
+// Evaluates a sequence of expressions, returning the value of the last one.
+// An empty body evaluates to the empty list.
+fn eval_body(body: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut res = LisperExp::List(vec![].into());
+    for exp in body.iter() {
+        res = eval(exp.clone(), env)?;
+    }
+    Ok(res)
+}
+
+// Evaluates all but the last expression of a body, leaving the last one in
+// tail position
+fn eval_body_tail(body: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, LisperErr> {
+    match body.split_last() {
+        Some((last, init)) => {
+            for exp in init.iter() {
+                eval(exp.clone(), env)?;
+            }
+            Ok(LisperTail::Eval(last.clone(), env.clone()))
+        },
+        None => Ok(LisperTail::Done(LisperExp::List(vec![].into()))),
+    }
+}
+
+// Evaluates (if test then else), only evaluating the branch that is taken.
+// The else branch is optional and defaults to the empty list.
+fn eval_if(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, LisperErr> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(LisperErr::new(LisperErrKind::SyntaxError, "if expects a test, a then branch and an optional else branch."));
+    }
+
+    if is_truthy(&eval(args[0].clone(), env)?) {
+        Ok(LisperTail::Eval(args[1].clone(), env.clone()))
+    } else {
+        eval_body_tail(&args[2..], env)
+    }
+}
+
+// Evaluates (cond (test body...) ... (else body...)), running the body of the
+// first clause whose test is truthy. A clause without a body returns its test.
+fn eval_cond(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperTail, LisperErr> {
+    for (i, clause) in args.iter().enumerate() {
+        let (test, body) = match clause {
+            LisperExp::List(list) if !list.is_empty() => (&list[0], &list[1..]),
+            other => return Err(
+                LisperErr::new(LisperErrKind::SyntaxError, format!("cond expects (test body...) clauses, got {}.", other))
+            ),
+        };
+
+        if let LisperExp::Symbol(sym) = test {
+            if sym == "else" {
+                if i != args.len() - 1 {
+                    return Err(LisperErr::new(LisperErrKind::SyntaxError, "else must be the last cond clause."));
+                }
+                return eval_body_tail(body, env);
+            }
+        }
+
+        let test_res = eval(test.clone(), env)?;
+        if is_truthy(&test_res) {
+            if body.is_empty() {
+                return Ok(LisperTail::Done(test_res));
+            }
+            return eval_body_tail(body, env);
+        }
+    }
+
+    Ok(LisperTail::Done(LisperExp::List(vec![].into())))
+}
+
+// Evaluates (when test body...) and (unless test body...). The body only runs
+// if the truthiness of the test matches expected.
+fn eval_when(args: &[LisperExp], env: &mut LisperEnv, expected: bool) -> Result<LisperTail, LisperErr> {
+    let (test, body) = args.split_first()
+    .ok_or(
+        LisperErr::new(LisperErrKind::SyntaxError, "when and unless expect a test.")
+    )?;
+
+    if is_truthy(&eval(test.clone(), env)?) == expected {
+        eval_body_tail(body, env)
+    } else {
+        Ok(LisperTail::Done(LisperExp::List(vec![].into())))
+    }
+}
+
+// Returns the value of a real number argument as a float, or a type error
+// naming the function and the position of the argument
+fn expect_number(func: &str, arg: &LisperExp, pos: usize) -> Result<f64, LisperErr> {
+    Ok(numbers::expect_real(func, arg, pos)?.to_f64())
+}
+
+// Returns the value of a string argument, or a type error
+fn expect_string<'a>(func: &str, arg: &'a LisperExp, pos: usize) -> Result<&'a str, LisperErr> {
+    match arg {
+        LisperExp::Str(s) => Ok(s),
+        other => Err(type_error(func, pos, "string", other)),
+    }
+}
+
+// Returns the items of a list argument, or a type error
+fn expect_list<'a>(func: &str, arg: &'a LisperExp, pos: usize) -> Result<&'a [LisperExp], LisperErr> {
+    match arg {
+        LisperExp::List(items) => Ok(items),
+        other => Err(type_error(func, pos, "list", other)),
+    }
+}
+
+// Returns the value of a whole, non-negative number argument used as an index
+fn expect_index(func: &str, arg: &LisperExp, pos: usize) -> Result<usize, LisperErr> {
+    match arg {
+        LisperExp::Int(n) if *n >= 0 => Ok(*n as usize),
+        LisperExp::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        other => Err(type_error(func, pos, "non-negative integer", other)),
+    }
+}
+
+// Creates an error for an index past the end of a string or list
+fn index_error(func: &str, index: usize, len: usize) -> LisperErr {
+    LisperErr::new(
+        LisperErrKind::IndexOutOfRange,
+        format!("{} index {} is out of range for length {}.", func, index, len)
+    )
+}
+
+// Creates an error for an argument of the wrong type, pos is zero based
+fn type_error(func: &str, pos: usize, expected: &str, actual: &LisperExp) -> LisperErr {
+    LisperErr::new(LisperErrKind::TypeError, format!(
+        "{} expects a {} as argument {}, got {} {}.",
+        func, expected, pos + 1, actual.type_name(), actual
+    ))
+}
+
+fn add(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    numbers::fold("+", args, numbers::add)
+}
+
+// With a single number, - negates it
+fn sub(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match args {
+        [arg] => Ok(numbers::negate("-", numbers::expect_num("-", arg, 0)?)?.into()),
+        _ => numbers::fold("-", args, numbers::sub),
+    }
+}
+
+fn mul(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    numbers::fold("*", args, numbers::mul)
+}
+
+fn div(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    numbers::fold("/", args, numbers::div)
+}
+
+fn modulus(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    numbers::fold("%", args, numbers::modulus)
+}
+
+// Checks that the test holds for the order of every adjacent pair of numbers,
+// NaN is not ordered so any test with it is false
+fn compare_numbers(name: &str, args: &[LisperExp], test: fn(Ordering) -> bool) -> Result<LisperExp, LisperErr> {
+    let mut nums: Vec<numbers::Num> = vec![];
+    for (i, arg) in args.iter().enumerate() {
+        nums.push(numbers::expect_real(name, arg, i)?);
+    }
+    Ok(LisperExp::Bool(nums.windows(2).all(|pair| numbers::compare(&pair[0], &pair[1]).is_some_and(test))))
+}
+
+fn less_than(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    compare_numbers("<", args, |ord| ord == Ordering::Less)
+}
+
+fn more_than(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    compare_numbers(">", args, |ord| ord == Ordering::Greater)
+}
+
+fn less_or_equal(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    compare_numbers("<=", args, |ord| ord != Ordering::Greater)
+}
+
+fn more_or_equal(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    compare_numbers(">=", args, |ord| ord != Ordering::Less)
+}
+
+// Like equals, but numbers are compared by value, so 1 and 1.0 are equal
+fn numeric_equals(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::Bool(args.windows(2).all(|pair| {
+        match (numbers::Num::from_exp(&pair[0]), numbers::Num::from_exp(&pair[1])) {
+            (Some(a), Some(b)) => numbers::equal(&a, &b),
+            _ => pair[0] == pair[1],
+        }
+    })))
+}
+
+fn equals(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::Bool(args.windows(2).all(|pair| pair[0] == pair[1])))
+}
+
+fn error(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let parts: Vec<String> = args.iter().map(LisperExp::display).collect();
+    Err(LisperErr::new(LisperErrKind::UserError, parts.join(" ")))
+}
+
+fn sin(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    numbers::unary("sin", &args[0], f64::sin, Complex::sin)
+}
+
+fn cos(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    numbers::unary("cos", &args[0], f64::cos, Complex::cos)
+}
+
+fn tan(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    numbers::unary("tan", &args[0], f64::tan, Complex::tan)
+}
+
+#[cfg(test)]
+mod tests {
+
+    // Tokenizes, parses and evaluates a single expression in the given env
+    pub(crate) fn eval_str(expr: &str, env: &mut super::LisperEnv) -> Result<super::LisperExp, super::LisperErr> {
+        let tokens = super::tokenize(expr.to_string());
+        let (parsed, _) = super::parse(&tokens)?;
+        super::eval(parsed, env)
+    }
+
+    #[test]
+    fn tokenize_expr() {
+        use super::*;
+
+        assert_eq!(tokenize("(+ 1 1)".to_string()), ["(", "+", "1", "1", ")"]);
+    }
+
+    #[test]
+    fn print_round_trip() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let sources = [
+            "(1 2.5 -3 (a b) () \"say \\\"hi\\\"\\n\" true)",
+            "(2.0 -0.0 1e300 9223372036854775807 -123456789012345678901234567890 -1/3)",
+            "'(quote x)",
+            "`(a ,b ,@c)",
+            "(quote)",
+            "(quote a b)",
+            "(unquote @x)",
+            "\"\\u{7}\"",
+        ];
+        for source in sources.iter() {
+            let (exp, _) = parse(&tokenize(source.to_string()))?;
+            let printed = print(&exp);
+            let (read_back, _) = parse(&tokenize(printed.clone()))?;
+            assert_eq!(format!("{:?}", read_back), format!("{:?}", exp), "{}", printed);
+        }
+
+        let (exp, _) = parse(&tokenize("(quote (1 (quasiquote x)))".to_string()))?;
+        assert_eq!(print(&exp), "'(1 `x)");
+        assert_eq!(exp.to_string(), print(&exp));
+
+        // Very large and very small floats are printed with an exponent
+        let floats = [1.5e300, -2.5e-300, 5e-324, f64::MAX, 1e16, 1e-5, 123.456, 1e15, 0.0001];
+        for n in floats.iter() {
+            let printed = print(&LisperExp::Number(*n));
+            assert!(printed.len() <= 24, "{}", printed);
+            let (read_back, _) = parse(&tokenize(printed.clone()))?;
+            assert_eq!(read_back, LisperExp::Number(*n), "{}", printed);
+        }
+        assert_eq!(print(&LisperExp::Number(1.5e300)), "1.5e300");
+        assert_eq!(print(&LisperExp::Number(1e15)), "1000000000000000.0");
+        assert_eq!(print(&LisperExp::Number(0.00001)), "1e-5");
+        Ok(())
+    }
+
+    #[test]
+    fn display_exp() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let (exp, _) = parse(&tokenize("(\"a\\tb\" (\"c\") d)".to_string()))?;
+        assert_eq!(exp.display(), "(a\tb (c) d)");
+        assert_eq!(exp.to_string(), "(\"a\\tb\" (\"c\") d)");
+        Ok(())
+    }
+
+    #[test]
+    fn lex_comments() {
+        use super::*;
+
+        assert_eq!(tokenize("(+ 1; one\n 2) ; two".to_string()), ["(", "+", "1", "2", ")"]);
+        assert_eq!(tokenize("#| a #| nested |# b |# x".to_string()), ["x"]);
+        assert_eq!(tokenize("\"; not a comment\"".to_string()), ["\"; not a comment\""]);
+        assert_eq!(tokenize("#;(a b) c".to_string()), ["#;", "(", "a", "b", ")", "c"]);
+
+        // Tokens after a comment keep their position
+        let tokens = lex("#| one\ntwo |# x");
+        assert_eq!((tokens[0].span.line, tokens[0].span.column), (2, 8));
+    }
+
+    #[test]
+    fn parse_comments() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        let source = "
+            ; Adds one
+            (defn inc (x) #| the argument |# (+ x 1))
+            #;(inc 100)
+            (list (inc 1) #;(inc 2) #; #; 3 4 5 #;6)
+            #;7";
+        assert_eq!(eval_program(&read_all(source)?, env)?.to_string(), "(2 5)");
+        assert_eq!(read_all("; nothing but a comment")?.len(), 0);
+
+        match read_all("(a) #| open") {
+            Err(err) => {
+                assert_eq!(err.message, "unterminated block comment, missing |#.");
+                assert_eq!(err.span.map(|span| span.column), Some(5));
+            },
+            _ => panic!("Unexpected result")
+        }
+        assert!(read_all("(a #;)").is_err());
+        assert!(read_all("#;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_expr() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_tokens = ["(".to_string(), "+".to_string(), "1".to_string(), "1".to_string(), ")".to_string()];
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        let (parsed_tokens, _) = parse(&mock_tokens[..])?;
+        match parsed_tokens {
+            LisperExp::List(list) => assert_eq!(list.len(), 3),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_number_expr() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_token = "99";
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        match parse_token(mock_token)? {
+            LisperExp::Int(num) => assert_eq!(num, 99),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_symbol_expr() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_token = "+";
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        match parse_token(mock_token)? {
+            LisperExp::Symbol(sym) => assert_eq!(sym.to_string(), "+".to_string()),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_bool_expr() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_token = "true";
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        match parse_token(mock_token)? {
+            LisperExp::Bool(b) => assert!(b),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_expr_complex() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_tokens = ["(".to_string(), "+".to_string(), "1".to_string(), ")".to_string(), "(".to_string(), "*".to_string(), "2".to_string(), "2".to_string(), ")".to_string()];
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        let (parsed_tokens, _) = parse(&mock_tokens[..])?;
+        match parsed_tokens {
+            LisperExp::List(list) => assert_eq!(list.len(), 2),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_add() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("+") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 52.0;
+        let arg1_f64: f64 = 13.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Number(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 + arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_sub() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("-") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 52.0;
+        let arg1_f64: f64 = 13.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Number(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 - arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        // A single argument is negated
+        assert_eq!(lisper_func.call(&[LisperExp::Int(5)], &mut env)?, LisperExp::Int(-5));
+        assert_eq!(eval_str("(- 5)", &mut env)?.to_string(), "-5");
+        assert_eq!(eval_str("(- -2.5)", &mut env)?.to_string(), "2.5");
+        assert_eq!(eval_str("(- 0.0)", &mut env)?.to_string(), "-0.0");
+        assert_eq!(eval_str("(- 1/3)", &mut env)?.to_string(), "-1/3");
+        assert_eq!(eval_str("(- -9223372036854775808)", &mut env)?.to_string(), "9223372036854775808");
+        assert_eq!(eval_str("(- inf)", &mut env)?.to_string(), "-inf");
+        assert_eq!(eval_str("(- 1+2i)", &mut env)?.to_string(), "-1-2i");
+        assert!(eval_str("(- \"a\")", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_mul() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("*") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 52.0;
+        let arg1_f64: f64 = 13.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Number(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 * arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_div() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("/") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 52.0;
+        let arg1_f64: f64 = 13.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Number(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 / arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_mod() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("%") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 52.0;
+        let arg1_f64: f64 = 13.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Number(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 % arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_less_than() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("<") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 5.0;
+        let arg1_f64: f64 = 13.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Bool(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 < arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_more_than() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get(">") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 5.0;
+        let arg1_f64: f64 = 13.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Bool(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 > arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_equals() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("=") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 5.0;
+        let arg1_f64: f64 = 5.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Bool(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 == arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_less_or_equal() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("<=") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 6.0;
+        let arg1_f64: f64 = 5.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Bool(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 <= arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_more_or_equal() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get(">=") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = 3.0;
+        let arg1_f64: f64 = 5.0;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+
+        if let LisperExp::Bool(res) = lisper_func.call(&[arg0, arg1], &mut env)? {
+            assert_eq!(res, arg0_f64 >= arg1_f64);
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn chained_comparisons() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        // Every adjacent pair has to hold, not just the last one
+        assert_eq!(eval_str("(< 1 5 2 3)", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(< 1 2 3 5)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(>= 3 3 1)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(> 3 1 2)", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(< 1)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(= 2 2 3)", env)?, LisperExp::Bool(false));
+        assert!(eval_str("(< 1 2 \"3\")", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn equality() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert_eq!(eval_str("(= true true)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(= 'a 'a 'b)", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(equal? '(1 (\"a\" b)) (list 1 (list \"a\" 'b)))", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(equal? '(1 2) '(1 2 3))", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(eq? \"a\" \"a\")", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(eq? 1 \"1\")", env)?, LisperExp::Bool(false));
+
+        // Functions are only equal to themselves
+        eval_str("(defn f (x) x)", env)?;
+        assert_eq!(eval_str("(eq? f f)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(eq? f (fn (x) x))", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(eq? + +)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(eq? + -)", env)?, LisperExp::Bool(false));
+
+        // Results can be compared on the Rust side
+        let expected = LisperExp::List(vec![LisperExp::Int(1), LisperExp::Str("a".to_string())].into());
+        assert_eq!(eval_str("(list 1 \"a\")", env)?, expected);
+        assert_ne!(LisperExp::Number(f64::NAN), LisperExp::Number(f64::NAN));
+        Ok(())
+    }
+
+    #[test]
+    fn total_order() {
+        use super::*;
+        use std::cmp::Ordering;
+
+        let num = LisperExp::Number;
+        assert_eq!(num(1.0).total_cmp(&num(2.0)), Ordering::Less);
+        assert_eq!(LisperExp::Bool(true).total_cmp(&num(-5.0)), Ordering::Less);
+        assert_eq!(num(f64::NAN).total_cmp(&num(f64::INFINITY)), Ordering::Greater);
+        assert_eq!(LisperExp::Int(1).total_cmp(&num(1.0)), Ordering::Less);
+        assert_eq!(LisperExp::Int(1).total_cmp(&num(0.5)), Ordering::Greater);
+        let short = LisperExp::List(vec![num(1.0)].into());
+        let long = LisperExp::List(vec![num(1.0), num(0.0)].into());
+        assert_eq!(short.total_cmp(&long), Ordering::Less);
+        assert_eq!(long.total_cmp(&long.clone()), Ordering::Equal);
+    }
+
+    #[test]
+    fn create_default_env_sin() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("sin") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = core::f64::consts::PI;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+
+        if let LisperExp::Number(res) = lisper_func.call(&[arg0], &mut env)? {
+            assert_eq!(res, arg0_f64.sin());
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_cos() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("cos") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = core::f64::consts::PI;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+
+        if let LisperExp::Number(res) = lisper_func.call(&[arg0], &mut env)? {
+            assert_eq!(res, arg0_f64.cos());
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn create_default_env_tan() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        let mut env:LisperEnv = create_default_env();
+
+        let lisper_func = match env.get("tan") {
+            Some(LisperExp::Func(func)) => func,
+            _ => return Err(Box::new(LisperErr::new(LisperErrKind::UnboundSymbol, "env function not found")))
+        };
+        
+        let arg0_f64: f64 = core::f64::consts::PI;
+
+        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+
+        if let LisperExp::Number(res) = lisper_func.call(&[arg0], &mut env)? {
+            assert_eq!(res, arg0_f64.tan());
+        } else {
+            panic!("Unexpected result");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_def_number() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(def x (+ 40 2))", env)?;
+        match eval_str("(* x 2)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 84),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_def_function() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(def plus +)", env)?;
+        match eval_str("(plus 1 2)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 3),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_def_redefine() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(def x 1)", env)?;
+        eval_str("(def x (+ x 1))", env)?;
+        match eval_str("x", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 2),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_def_errors() {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert!(eval_str("(def 1 2)", env).is_err());
+        assert!(eval_str("(def x)", env).is_err());
+        assert!(eval_str("undefined", env).is_err());
+        assert!(eval_str("(1 2)", env).is_err());
+    }
+
+    #[test]
+    fn eval_if_branches() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        match eval_str("(if (< 1 2) 10 20)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 10),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(if (> 1 2) 10 20)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 20),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(if false 10)", env)? {
+            LisperExp::List(list) => assert!(list.is_empty()),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_if_is_lazy() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        // The branch that isn't taken would fail if it was evaluated
+        eval_str("(if true (def x 1) (def x undefined))", env)?;
+        eval_str("(if false (def x undefined) (def y 2))", env)?;
+        match eval_str("(+ x y)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 3),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_cond_clauses() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(def x 5)", env)?;
+        match eval_str("(cond ((< x 0) 1) ((< x 10) 2 3) (else undefined))", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 3),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(cond ((> x 10) 1) (else 4))", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 4),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(cond ((> x 10) 1))", env)? {
+            LisperExp::List(list) => assert!(list.is_empty()),
+            _ => panic!("Unexpected result")
+        }
+        assert!(eval_str("(cond (else 1) (true 2))", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn eval_when_unless() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        match eval_str("(when (= 1 1) (def x 2) (* x 3))", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 6),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(unless (= 1 1) undefined)", env)? {
+            LisperExp::List(list) => assert!(list.is_empty()),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(unless false 7)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 7),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn truthiness() {
+        use super::*;
+
+        assert!(is_truthy(&LisperExp::Bool(true)));
+        assert!(!is_truthy(&LisperExp::Bool(false)));
+        assert!(is_truthy(&LisperExp::Number(0.0)));
+        assert!(is_truthy(&LisperExp::Symbol("x".to_string())));
+        assert!(!is_truthy(&LisperExp::List(vec![].into())));
+        assert!(is_truthy(&LisperExp::List(vec![LisperExp::Bool(false)].into())));
+    }
+
+    #[test]
+    fn eval_fn_call() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        match eval_str("((fn (x y) (* x y)) 6 7)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 42),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("((lambda () 1 2))", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 2),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_defn_recursion() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(defn fact (n) (if (<= n 1) 1 (* n (fact (- n 1)))))", env)?;
+        match eval_str("(fact 5)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 120),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_fn_closures() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        // Functions can be returned from and passed to other functions
+        eval_str("(defn make-adder (n) (fn (x) (+ x n)))", env)?;
+        eval_str("(def add5 (make-adder 5))", env)?;
+        eval_str("(defn twice (f x) (f (f x)))", env)?;
+        match eval_str("(twice add5 1)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 11),
+            _ => panic!("Unexpected result")
+        }
+
+        // The parameter doesn't leak out of the call
+        assert!(eval_str("n", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn eval_fn_rest_params() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(defn tagged (tag & items) (cons tag items))", env)?;
+        assert_eq!(eval_str("(tagged 'a 1 2)", env)?.to_string(), "(a 1 2)");
+        assert_eq!(eval_str("(tagged 'a)", env)?.to_string(), "(a)");
+        match eval_str("(tagged)", env) {
+            Err(err) => assert_eq!(err.message, "tagged expects at least 1 arguments, got 0."),
+            _ => panic!("Unexpected result")
+        }
+        assert!(eval_str("(fn (a & b c) a)", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn eval_fn_errors() {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert!(eval_str("((fn (x) x))", env).is_err());
+        assert!(eval_str("((fn (x) x) 1 2)", env).is_err());
+        assert!(eval_str("(fn (1) 1)", env).is_err());
+        assert!(eval_str("(fn x x)", env).is_err());
+    }
+
+    #[test]
+    fn env_scopes() {
+        use super::*;
+
+        let global = LisperEnv::new();
+        global.define("x", LisperExp::Number(1.0));
+        global.define("y", LisperExp::Number(2.0));
+
+        let local = global.child();
+        local.define("x", LisperExp::Number(10.0));
+
+        // Lookups walk outwards, and local bindings shadow outer ones
+        match (local.get("x"), local.get("y"), global.get("x")) {
+            (Some(LisperExp::Number(x)), Some(LisperExp::Number(y)), Some(LisperExp::Number(gx))) => {
+                assert_eq!(x, 10.0);
+                assert_eq!(y, 2.0);
+                assert_eq!(gx, 1.0);
+            },
+            _ => panic!("Unexpected result")
+        }
+
+        // set! changes the nearest binding
+        assert!(local.set("y", LisperExp::Number(3.0)).is_ok());
+        match global.get("y") {
+            Some(LisperExp::Number(y)) => assert_eq!(y, 3.0),
+            _ => panic!("Unexpected result")
+        }
+        assert!(local.set("z", LisperExp::Number(3.0)).is_err());
+    }
+
+    #[test]
+    fn eval_let_scope() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(def x 1)", env)?;
+        match eval_str("(let ((x 10) (y x)) (def z 3) (+ x y z))", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 14),
+            _ => panic!("Unexpected result")
+        }
+
+        // The let scope is gone after the body
+        assert!(eval_str("y", env).is_err());
+        assert!(eval_str("z", env).is_err());
+        match eval_str("x", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 1),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_let_star_and_letrec() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        // let* sees the earlier bindings, let sees the outer ones
+        eval_str("(def x 1)", env)?;
+        assert_eq!(eval_str("(let* ((x 10) (y (+ x 1))) (list x y))", env)?.to_string(), "(10 11)");
+        assert_eq!(eval_str("(let ((x 10) (y (+ x 1))) (list x y))", env)?.to_string(), "(10 2)");
+        assert_eq!(eval_str("(let* ((x 2) (f (fn () x)) (x 3)) (list (f) x))", env)?.to_string(), "(2 3)");
+
+        eval_str(
+            "(defn parity (n)
+                (letrec ((even? (fn (n) (if (= n 0) true (odd? (- n 1)))))
+                         (odd? (fn (n) (if (= n 0) false (even? (- n 1))))))
+                    (even? n)))",
+            env
+        )?;
+        assert_eq!(eval_str("(parity 10)", env)?.to_string(), "true");
+        assert_eq!(eval_str("(parity 7)", env)?.to_string(), "false");
+
+        // None of the bindings outlive the body
+        assert!(eval_str("y", env).is_err());
+        assert!(eval_str("odd?", env).is_err());
+        assert!(eval_str("(let* (x 1) x)", env).is_err());
+        assert!(eval_str("(letrec ((1 2)) 1)", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn eval_named_let() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert_eq!(eval_str("(let loop ((i 0) (acc '())) (if (= i 3) acc (loop (+ i 1) (cons i acc))))", env)?.to_string(), "(2 1 0)");
+
+        // Looping is a tail call, so it runs in constant stack space
+        assert_eq!(eval_str("(let count ((n 100000)) (if (= n 0) 'done (count (- n 1))))", env)?.to_string(), "done");
+
+        assert!(eval_str("loop", env).is_err());
+        match eval_str("(let loop ((i 0)) (loop))", env) {
+            Err(err) => assert_eq!(err.message, "loop expects 1 arguments, got 0."),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_program_forms() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        let forms = read_all("(def x 1)\n(def y (+ x 1)) (* x y 3)")?;
+        assert_eq!(forms.len(), 3);
+        assert_eq!(eval_program(&forms, env)?.to_string(), "6");
+        assert_eq!(eval_program(&read_all("")?, env)?.to_string(), "()");
+
+        // Every form keeps its own span
+        let spans: Vec<usize> = parse_program(&lex("1\n  (a)"))?.iter().map(|form| form.span().line).collect();
+        assert_eq!(spans, vec![1, 2]);
+
+        match read_all("(def z 1) (+ z") {
+            Err(err) => assert_eq!(err.span.map(|span| span.column), Some(11)),
+            _ => panic!("Unexpected result")
+        }
+        assert!(read_all("1 2)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn eval_error_spans() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        // Errors raised while evaluating point at the innermost form they
+        // came from
+        let env = &mut create_default_env();
+        match eval_program(&read_all("(def x 1)\n(+ x (* 2 nope))")?, env) {
+            Err(err) => {
+                assert!(matches!(err.kind, LisperErrKind::UnboundSymbol));
+                assert_eq!(err.span, Some(LisperSpan { start: 15, end: 25, line: 2, column: 6 }));
+                assert!(err.to_string().contains("(line 2, column 6)"));
+            },
+            _ => panic!("Unexpected result")
+        }
+
+        // Errors in a function body point at the body, not at the call
+        let source = "(defn f (a)\n  (+ a \"b\"))\n(f 1)";
+        match eval_program(&read_all(source)?, env) {
+            Err(err) => {
+                assert!(matches!(err.kind, LisperErrKind::TypeError));
+                assert_eq!(err.span.map(|span| (span.line, span.column)), Some((2, 3)));
+                assert_eq!(err.stack, vec!["f"]);
+            },
+            _ => panic!("Unexpected result")
+        }
+        match eval_program(&read_all("1\n  (error \"oops\")")?, env) {
+            Err(err) => assert_eq!(err.span.map(|span| (span.line, span.column)), Some((2, 3))),
+            _ => panic!("Unexpected result")
+        }
+
+        // Expressions without source, like those from parse, have no span
+        match eval_str("(+ 1 nope)", env) {
+            Err(err) => assert_eq!(err.span, None),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_begin() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert_eq!(eval_str("(begin (def a 1) (set! a (+ a 1)) a)", env)?.to_string(), "2");
+        assert_eq!(eval_str("(do 1 2 3)", env)?.to_string(), "3");
+        assert_eq!(eval_str("(begin)", env)?.to_string(), "()");
+
+        // The last expression is a tail call
+        eval_str("(defn count (n) (if (= n 0) 'done (begin (def last n) (count (- n 1)))))", env)?;
+        assert_eq!(eval_str("(count 100000)", env)?.to_string(), "done");
+        Ok(())
+    }
+
+    #[test]
+    fn eval_set_closure_counter() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(defn make-counter () (let ((n 0)) (fn () (set! n (+ n 1)))))", env)?;
+        eval_str("(def counter (make-counter))", env)?;
+        eval_str("(counter)", env)?;
+        eval_str("(counter)", env)?;
+        match eval_str("(counter)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 3),
+            _ => panic!("Unexpected result")
+        }
+
+        // A new counter gets its own scope
+        match eval_str("((make-counter))", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 1),
+            _ => panic!("Unexpected result")
+        }
+        assert!(eval_str("(set! undefined 1)", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn eval_global_scope_shared() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        // Functions see globals defined after them, and can set! them
+        eval_str("(defn get-total () total)", env)?;
+        eval_str("(defn add-total (n) (set! total (+ total n)))", env)?;
+        eval_str("(def total 1)", env)?;
+        eval_str("(add-total 4)", env)?;
+        match eval_str("(get-total)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 5),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn register_closure() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        use std::cell::Cell;
+
+        let env = &mut create_default_env();
+
+        // A builtin that captures state from the host
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        env.register("tick", LisperArity::Range(0, 1), "Increments the counter.", move |args, _env| {
+            let step = match args.first() {
+                Some(LisperExp::Int(n)) => *n,
+                _ => 1,
+            };
+            counter.set(counter.get() + step);
+            Ok(LisperExp::Int(counter.get()))
+        });
+
+        eval_str("(tick)", env)?;
+        eval_str("(tick 5)", env)?;
+        assert_eq!(count.get(), 6);
+
+        match env.get("tick") {
+            Some(LisperExp::Func(func)) => {
+                assert_eq!(func.name, "tick");
+                assert_eq!(func.arity, LisperArity::Range(0, 1));
+                assert_eq!(func.doc, "Increments the counter.");
+            },
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn register_arity() {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert!(eval_str("(sin)", env).is_err());
+        assert!(eval_str("(sin 1 2)", env).is_err());
+        assert!(eval_str("(-)", env).is_err());
+        assert!(eval_str("(+)", env).is_ok());
+
+        assert!(LisperArity::Exact(2).accepts(2));
+        assert!(!LisperArity::Exact(2).accepts(3));
+        assert!(LisperArity::AtLeast(1).accepts(5));
+        assert!(!LisperArity::Range(1, 2).accepts(0));
     }
-    LisperExp::Number(res)
-}
 
-fn tan(args: &LisperExp) -> LisperExp {
-    let mut res = 0.0;
-    if let LisperExp::List(list) = args {
-        if let LisperExp::Number(n) = list[0] {
-            res = n.tan();
+    #[test]
+    fn builtin_type_errors() {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        match eval_str("(+ 1 true 2)", env) {
+            Err(err) => {
+                assert_eq!(err.kind, LisperErrKind::TypeError);
+                assert_eq!(err.message, "+ expects a number as argument 2, got bool true.");
+            },
+            _ => panic!("Unexpected result")
         }
-    }
-    LisperExp::Number(res)
-}
+        match eval_str("(< 1 (fn (x) x))", env) {
+            Err(err) => assert!(err.message.contains("got function")),
+            _ => panic!("Unexpected result")
+        }
+        assert!(eval_str("(sin false)", env).is_err());
+        assert!(eval_str("(* 2 (< 1 2))", env).is_err());
 
-#[cfg(test)]
-mod tests {
+        // Errors inside function bodies are passed up through eval
+        assert!(eval_str("((fn (x) (+ x 1)) true)", env).is_err());
+    }
 
     #[test]
-    fn tokenize_expr() {
+    fn error_kinds() {
         use super::*;
 
-        assert_eq!(tokenize("(+ 1 1)".to_string()), ["(", "+", "1", "1", ")"]);
+        let env = &mut create_default_env();
+
+        let kind = |expr: &str, env: &mut LisperEnv| eval_str(expr, env).map_err(|err| err.kind).err();
+        assert_eq!(kind("(+ 1", env), Some(LisperErrKind::ParseError));
+        assert_eq!(kind("undefined", env), Some(LisperErrKind::UnboundSymbol));
+        assert_eq!(kind("(+ 1 false)", env), Some(LisperErrKind::TypeError));
+        assert_eq!(kind("(1 2)", env), Some(LisperErrKind::TypeError));
+        assert_eq!(kind("(sin 1 2)", env), Some(LisperErrKind::ArityError));
+        assert_eq!(kind("((fn (x) x))", env), Some(LisperErrKind::ArityError));
+        assert_eq!(kind("(/ 1 0)", env), Some(LisperErrKind::DivisionByZero));
+        assert_eq!(kind("(% 1 0)", env), Some(LisperErrKind::DivisionByZero));
+        assert_eq!(kind("(error 42)", env), Some(LisperErrKind::UserError));
+        assert_eq!(kind("(def 1 2)", env), Some(LisperErrKind::SyntaxError));
     }
 
     #[test]
-    fn parse_expr() -> Result<(),  Box<dyn std::error::Error>> {
+    fn error_stack() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_tokens = ["(".to_string(), "+".to_string(), "1".to_string(), "1".to_string(), ")".to_string()];
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        let (parsed_tokens, _) = parse(&mock_tokens[..])?;
-        match parsed_tokens {
-            LisperExp::List(list) => assert_eq!(list.len(), 3),
-            _ => assert!(false)
+
+        let env = &mut create_default_env();
+
+        eval_str("(defn inner (x) (error x (* x 2)))", env)?;
+        eval_str("(defn outer (x) (+ 1 (inner x)))", env)?;
+        match eval_str("(outer 5)", env) {
+            Err(err) => {
+                assert_eq!(err.kind, LisperErrKind::UserError);
+                assert_eq!(err.message, "5 10");
+                assert_eq!(err.stack, vec!["inner".to_string(), "outer".to_string()]);
+                assert_eq!(err.to_string(), "Error: 5 10\n    in inner\n    in outer");
+            },
+            _ => panic!("Unexpected result")
         }
         Ok(())
     }
 
     #[test]
-    fn parse_number_expr() -> Result<(),  Box<dyn std::error::Error>> {
+    fn error_span_display() {
         use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_token = "99";
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        match parse_token(&mock_token) {
-            LisperExp::Number(num) => assert_eq!(num, 99.0),
-            _ => assert!(false)
-        }
-        Ok(())
+
+        let span = LisperSpan { start: 4, end: 5, line: 2, column: 3 };
+        let err = LisperErr::new(LisperErrKind::ParseError, "missing ).").with_span(span);
+        assert_eq!(err.span, Some(span));
+        assert_eq!(err.to_string(), "Parse error: missing ). (line 2, column 3)");
     }
 
     #[test]
-    fn parse_symbol_expr() -> Result<(),  Box<dyn std::error::Error>> {
+    fn lex_spans() {
         use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_token = "+";
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        match parse_token(&mock_token) {
-            LisperExp::Symbol(sym) => assert_eq!(sym.to_string(), "+".to_string()),
-            _ => assert!(false)
-        }
-        Ok(())
+
+        let tokens = lex("(+ 1\n  (* 22 3))");
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+        assert_eq!(texts, ["(", "+", "1", "(", "*", "22", "3", ")", ")"]);
+
+        assert_eq!(tokens[0].span, LisperSpan { start: 0, end: 1, line: 1, column: 1 });
+        assert_eq!(tokens[2].span, LisperSpan { start: 3, end: 4, line: 1, column: 4 });
+        assert_eq!(tokens[3].span, LisperSpan { start: 7, end: 8, line: 2, column: 3 });
+        assert_eq!(tokens[5].span, LisperSpan { start: 10, end: 12, line: 2, column: 6 });
     }
 
     #[test]
-    fn parse_bool_expr() -> Result<(),  Box<dyn std::error::Error>> {
+    fn lex_columns_count_chars() {
         use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_token = "true";
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        match parse_token(&mock_token) {
-            LisperExp::Bool(b) => assert!(b),
-            _ => assert!(false)
-        }
-        Ok(())
+
+        let tokens = lex("(π→ x)");
+        assert_eq!(tokens[2].text, "x");
+        assert_eq!(tokens[2].span.column, 5);
+        assert_eq!(tokens[2].span.start, 7);
     }
 
     #[test]
-    fn parse_expr_complex() -> Result<(),  Box<dyn std::error::Error>> {
+    fn parse_spanned_tree() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_tokens = ["(".to_string(), "+".to_string(), "1".to_string(), ")".to_string(), "(".to_string(), "*".to_string(), "2".to_string(), "2".to_string(), ")".to_string()];
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        let (parsed_tokens, _) = parse(&mock_tokens[..])?;
-        match parsed_tokens {
-            LisperExp::List(list) => assert_eq!(list.len(), 2),
-            _ => assert!(false)
+
+        let tokens = lex("(+ 1\n  (* 2 3))");
+        let (syntax, rest) = parse_spanned(&tokens)?;
+        assert!(rest.is_empty());
+        assert_eq!(syntax.span(), LisperSpan { start: 0, end: 15, line: 1, column: 1 });
+        match &syntax {
+            LisperSyntax::List(items, _) => {
+                assert_eq!(items.len(), 3);
+                assert_eq!(items[2].span(), LisperSpan { start: 7, end: 14, line: 2, column: 3 });
+            },
+            _ => panic!("Unexpected result")
         }
+        assert_eq!(syntax.to_exp().to_string(), "(+ 1 (* 2 3))");
         Ok(())
     }
 
     #[test]
-    fn create_default_env_add() -> Result<(),  Box<dyn std::error::Error>> {
+    fn parse_spanned_errors() {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
-
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("+")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 52.0;
-        let arg1_f64: f64 = 13.0;
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
-
-        if let LisperExp::Number(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 + arg1_f64);
-        } else {
-            assert!(false);
+        // The error points at the ( that was never closed
+        match parse_spanned(&lex("(+ 1\n  (* 2 3)")) {
+            Err(err) => {
+                assert_eq!(err.kind, LisperErrKind::ParseError);
+                assert_eq!(err.span, Some(LisperSpan { start: 0, end: 1, line: 1, column: 1 }));
+            },
+            _ => panic!("Unexpected result")
+        }
+        match parse_spanned(&lex("  )")) {
+            Err(err) => assert_eq!(err.span.map(|span| span.column), Some(3)),
+            _ => panic!("Unexpected result")
+        }
+        // Plain tokens have no positions to report
+        match parse(&tokenize("(+ 1".to_string())) {
+            Err(err) => assert_eq!(err.span, None),
+            _ => panic!("Unexpected result")
         }
-
-        Ok(())
     }
 
     #[test]
-    fn create_default_env_sub() -> Result<(),  Box<dyn std::error::Error>> {
+    fn lex_string() {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
 
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("-")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 52.0;
-        let arg1_f64: f64 = 13.0;
+        let tokens = tokenize("(f \"hello (world)\" \"a\\\"b\"x)".to_string());
+        assert_eq!(tokens, ["(", "f", "\"hello (world)\"", "\"a\\\"b\"", "x", ")"]);
+    }
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+    #[test]
+    fn parse_string_escapes() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
 
-        if let LisperExp::Number(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 - arg1_f64);
-        } else {
-            assert!(false);
+        match parse_token(r#""a\nb\t\"c\"\\d\u{3c0}""#)? {
+            LisperExp::Str(s) => assert_eq!(s, "a\nb\t\"c\"\\d\u{3c0}"),
+            _ => panic!("Unexpected result")
         }
-
+        match parse_token("\"\"")? {
+            LisperExp::Str(s) => assert_eq!(s, ""),
+            _ => panic!("Unexpected result")
+        }
+        assert!(parse_token("\"abc").is_err());
+        assert!(parse_token("\"").is_err());
+        assert!(parse_token(r#""\q""#).is_err());
+        assert!(parse_token(r#""\u{110000}""#).is_err());
+        assert!(parse_token(r#""\u{41""#).is_err());
         Ok(())
     }
 
     #[test]
-    fn create_default_env_mul() -> Result<(),  Box<dyn std::error::Error>> {
+    fn display_string_escapes() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
 
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("*")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 52.0;
-        let arg1_f64: f64 = 13.0;
-
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+        let string = LisperExp::Str("say \"hi\"\n\t\\ π\u{7}".to_string());
+        assert_eq!(string.to_string(), r#""say \"hi\"\n\t\\ π\u{7}""#);
 
-        if let LisperExp::Number(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 * arg1_f64);
-        } else {
-            assert!(false);
+        // The escaped string reads back as the same string
+        match parse_token(&string.to_string())? {
+            LisperExp::Str(s) => assert_eq!(s, "say \"hi\"\n\t\\ π\u{7}"),
+            _ => panic!("Unexpected result")
         }
-
         Ok(())
     }
 
     #[test]
-    fn create_default_env_div() -> Result<(),  Box<dyn std::error::Error>> {
+    fn eval_string() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
-
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("/")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 52.0;
-        let arg1_f64: f64 = 13.0;
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+        let env = &mut create_default_env();
 
-        if let LisperExp::Number(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 / arg1_f64);
-        } else {
-            assert!(false);
+        eval_str("(def greeting \"hello world\")", env)?;
+        match eval_str("greeting", env)? {
+            LisperExp::Str(s) => assert_eq!(s, "hello world"),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(error \"bad value:\" 5)", env) {
+            Err(err) => assert_eq!(err.message, "bad value: 5"),
+            _ => panic!("Unexpected result")
+        }
+        match parse_spanned(&lex("(f \"abc)")) {
+            Err(err) => assert_eq!(err.span.map(|span| span.column), Some(4)),
+            _ => panic!("Unexpected result")
         }
-
         Ok(())
     }
 
     #[test]
-    fn create_default_env_mod() -> Result<(),  Box<dyn std::error::Error>> {
+    fn tokenize_quotes() {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
 
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("%")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 52.0;
-        let arg1_f64: f64 = 13.0;
+        assert_eq!(tokenize("'(a `b ,c ,@d)".to_string()), ["'", "(", "a", "`", "b", ",", "c", ",@", "d", ")"]);
+        assert_eq!(tokenize("'a'b".to_string()), ["'", "a", "'", "b"]);
+    }
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+    #[test]
+    fn parse_quote_shorthand() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
 
-        if let LisperExp::Number(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 % arg1_f64);
-        } else {
-            assert!(false);
-        }
+        let (parsed, _) = parse(&tokenize("'(1 `x ,y ,@z)".to_string()))?;
+        assert_eq!(
+            parsed.to_string(),
+            "'(1 `x ,y ,@z)"
+        );
 
+        let (syntax, _) = parse_spanned(&lex(" 'abc"))?;
+        assert_eq!(syntax.span(), LisperSpan { start: 1, end: 5, line: 1, column: 2 });
+        assert!(parse(&tokenize("'".to_string())).is_err());
         Ok(())
     }
 
     #[test]
-    fn create_default_env_less_than() -> Result<(),  Box<dyn std::error::Error>> {
+    fn eval_quote() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
-
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("<")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 5.0;
-        let arg1_f64: f64 = 13.0;
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+        let env = &mut create_default_env();
 
-        if let LisperExp::Bool(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 < arg1_f64);
-        } else {
-            assert!(false);
+        match eval_str("'(1 2 3)", env)? {
+            LisperExp::List(list) => assert_eq!(list.len(), 3),
+            _ => panic!("Unexpected result")
         }
-
+        match eval_str("(quote undefined)", env)? {
+            LisperExp::Symbol(sym) => assert_eq!(sym, "undefined"),
+            _ => panic!("Unexpected result")
+        }
+        assert_eq!(eval_str("''a", env)?.to_string(), "'a");
+        assert!(eval_str("(quote)", env).is_err());
+        assert!(eval_str(",x", env).is_err());
         Ok(())
     }
 
     #[test]
-    fn create_default_env_more_than() -> Result<(),  Box<dyn std::error::Error>> {
+    fn eval_quasiquote() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
 
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get(">")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 5.0;
-        let arg1_f64: f64 = 13.0;
+        let env = &mut create_default_env();
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+        eval_str("(def xs '(3 4))", env)?;
+        assert_eq!(eval_str("`(1 ,(+ 1 1) ,@xs 5)", env)?.to_string(), "(1 2 3 4 5)");
+        assert_eq!(eval_str("`(a (b ,(+ 1 2)) c)", env)?.to_string(), "(a (b 3) c)");
+        assert_eq!(eval_str("`x", env)?.to_string(), "x");
+        assert_eq!(eval_str("`(,@'() 1)", env)?.to_string(), "(1)");
 
-        if let LisperExp::Bool(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 > arg1_f64);
-        } else {
-            assert!(false);
-        }
+        // Only the unquotes of the outer quasiquote are evaluated
+        assert_eq!(
+            eval_str("`(a `(b ,(c ,(+ 1 2))))", env)?.to_string(),
+            "(a `(b ,(c 3)))"
+        );
 
+        assert!(eval_str("`(1 ,@5)", env).is_err());
+        assert!(eval_str("`,@xs", env).is_err());
         Ok(())
     }
 
     #[test]
-    fn create_default_env_equals() -> Result<(),  Box<dyn std::error::Error>> {
+    fn eval_tail_calls() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
-
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("=")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 5.0;
-        let arg1_f64: f64 = 5.0;
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+        let env = &mut create_default_env();
 
-        if let LisperExp::Bool(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 == arg1_f64);
-        } else {
-            assert!(false);
+        // Tail calls through if, cond, when and let don't grow the stack
+        eval_str("(defn count-if (n acc) (if (= n 0) acc (count-if (- n 1) (+ acc 1))))", env)?;
+        eval_str("(defn count-cond (n acc) (cond ((= n 0) acc) (else (count-cond (- n 1) (+ acc 1)))))", env)?;
+        eval_str("(defn count-let (n) (let ((m (- n 1))) (when (> m 0) (count-let m))))", env)?;
+        match eval_str("(count-if 100000 0)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 100000),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(count-cond 100000 0)", env)? {
+            LisperExp::Int(num) => assert_eq!(num, 100000),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(count-let 100000)", env)? {
+            LisperExp::List(list) => assert!(list.is_empty()),
+            _ => panic!("Unexpected result")
         }
-
         Ok(())
     }
 
     #[test]
-    fn create_default_env_less_or_equal() -> Result<(),  Box<dyn std::error::Error>> {
+    fn eval_mutual_tail_calls() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
-
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("<=")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 6.0;
-        let arg1_f64: f64 = 5.0;
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+        let env = &mut create_default_env();
 
-        if let LisperExp::Bool(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 <= arg1_f64);
-        } else {
-            assert!(false);
+        eval_str("(defn is-even (n) (if (= n 0) true (is-odd (- n 1))))", env)?;
+        eval_str("(defn is-odd (n) (if (= n 0) false (is-even (- n 1))))", env)?;
+        match eval_str("(is-even 100001)", env)? {
+            LisperExp::Bool(b) => assert!(!b),
+            _ => panic!("Unexpected result")
         }
-
         Ok(())
     }
 
     #[test]
-    fn create_default_env_more_or_equal() -> Result<(),  Box<dyn std::error::Error>> {
+    fn eval_tail_call_error_frame() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
-
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get(">=")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = 3.0;
-        let arg1_f64: f64 = 5.0;
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
-        let arg1:LisperExp = LisperExp::Number(arg1_f64);
+        let env = &mut create_default_env();
 
-        if let LisperExp::Bool(res) = lisper_func(&LisperExp::List(vec![arg0, arg1])) {
-            assert_eq!(res, arg0_f64 >= arg1_f64);
-        } else {
-            assert!(false);
+        // The frame of a tail called function replaces the caller
+        eval_str("(defn fail (n) (if (= n 0) (error \"done\") (fail (- n 1))))", env)?;
+        eval_str("(defn start () (+ 1 (fail 3)))", env)?;
+        match eval_str("(start)", env) {
+            Err(err) => assert_eq!(err.stack, vec!["fail".to_string(), "start".to_string()]),
+            _ => panic!("Unexpected result")
         }
-
         Ok(())
     }
 
     #[test]
-    fn create_default_env_sin() -> Result<(),  Box<dyn std::error::Error>> {
+    fn eval_depth_exceeded() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
-
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("sin")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = core::f64::consts::PI;
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        let env = &mut create_default_env();
+        env.set_max_eval_depth(100);
 
-        if let LisperExp::Number(res) = lisper_func(&LisperExp::List(vec![arg0])) {
-            assert_eq!(res, arg0_f64.sin());
-        } else {
-            assert!(false);
+        eval_str("(defn sum (n) (if (= n 0) 0 (+ n (sum (- n 1)))))", env)?;
+        match eval_str("(sum 1000)", env) {
+            Err(err) => {
+                assert!(matches!(err.kind, LisperErrKind::DepthExceeded));
+                assert_eq!(err.message, "eval reached the maximum depth of 100.");
+                assert!(err.to_string().ends_with(" times)"));
+            },
+            _ => panic!("Unexpected result")
         }
 
+        // The depth unwinds after the error, so the env is still usable
+        match eval_str("(sum 10)", env)? {
+            LisperExp::Int(n) => assert_eq!(n, 55),
+            _ => panic!("Unexpected result")
+        }
         Ok(())
     }
 
     #[test]
-    fn create_default_env_cos() -> Result<(),  Box<dyn std::error::Error>> {
+    fn eval_default_depth_limit() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
-
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("cos")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = core::f64::consts::PI;
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+        // The default limits fit in the stack of a test thread, in debug
+        // builds too
+        let env = &mut create_default_env();
+        eval_str("(defn sum (n) (if (= n 0) 0 (+ n (sum (- n 1)))))", env)?;
+        match eval_str("(sum 100000)", env) {
+            Err(err) => assert!(matches!(err.kind, LisperErrKind::DepthExceeded)),
+            _ => panic!("Unexpected result")
+        }
 
-        if let LisperExp::Number(res) = lisper_func(&LisperExp::List(vec![arg0])) {
-            assert_eq!(res, arg0_f64.cos());
-        } else {
-            assert!(false);
+        // The stack limit leaves room for the default depth of plain
+        // recursion, in debug builds for at least half of it
+        assert_eq!(eval_str("(sum 500)", env)?, LisperExp::Int(125250));
+        if !cfg!(debug_assertions) {
+            assert_eq!(eval_str("(sum 990)", env)?, LisperExp::Int(490545));
         }
 
+        env.set_max_stack_size(1024);
+        match eval_str("(sum 100)", env) {
+            Err(err) => assert!(err.message.starts_with("eval ran out of stack space at depth ")),
+            _ => panic!("Unexpected result")
+        }
         Ok(())
     }
 
     #[test]
-    fn create_default_env_tan() -> Result<(),  Box<dyn std::error::Error>> {
+    fn deeply_nested_data() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
-        
-        let env:LisperEnv = create_default_env();
 
-        let lisper_func: &fn(&LisperExp) -> LisperExp = env.data.get("tan")
-        .ok_or(
-            LisperErr::Reason("Error, env function not found".to_string())
-        )?;
-        
-        let arg0_f64: f64 = core::f64::consts::PI;
+        // Lists nested deeper than the stack allows to recurse can still be
+        // copied, compared, sorted, printed and dropped
+        let env = &mut create_default_env();
+        eval_str("(defn nest (n) (let loop ((i 0) (acc '())) (if (= i n) acc (loop (+ i 1) (list acc)))))", env)?;
+        eval_str("(def a (nest 100000))", env)?;
+        eval_str("(def b (nest 100000))", env)?;
+        assert_eq!(eval_str("(equal? a b)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(equal? a (list b))", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(length (sort (list a b (nest 3))))", env)?, LisperExp::Int(3));
+        let printed = eval_str("a", env)?.to_string();
+        assert_eq!(printed.len(), 200002);
+        assert!(printed.starts_with("(((") && printed.ends_with(")))"));
+        assert_eq!(eval_str("(nest 2)", env)?.to_string(), "((()))");
+        eval_str("(nest 10000)", env)?;
+
+        // Quasiquote walks its template, so it is limited like eval
+        eval_str("(defmacro deep-template () (list 'quasiquote a))", env)?;
+        match eval_str("(deep-template)", env) {
+            Err(err) => assert!(matches!(err.kind, LisperErrKind::DepthExceeded)),
+            _ => panic!("Unexpected result")
+        }
+        Ok(())
+    }
 
-        let arg0:LisperExp = LisperExp::Number(arg0_f64);
+    #[test]
+    fn parse_depth_exceeded() {
+        use super::*;
 
-        if let LisperExp::Number(res) = lisper_func(&LisperExp::List(vec![arg0])) {
-            assert_eq!(res, arg0_f64.tan());
-        } else {
-            assert!(false);
+        let source = format!("{}1{}", "(".repeat(10000), ")".repeat(10000));
+        match parse_spanned(&lex(&source)) {
+            Err(err) => {
+                assert!(matches!(err.kind, LisperErrKind::DepthExceeded));
+                assert_eq!(err.message, format!("parse reached the maximum nesting depth of {}.", DEFAULT_MAX_PARSE_DEPTH));
+            },
+            _ => panic!("Unexpected result")
         }
 
-        Ok(())
+        // Quote shorthand counts as nesting too
+        let tokens = lex("(a '(b (c)))");
+        assert!(parse_spanned_with_limit(&tokens, 4).is_ok());
+        match parse_spanned_with_limit(&tokens, 3) {
+            Err(err) => {
+                assert!(matches!(err.kind, LisperErrKind::DepthExceeded));
+                assert_eq!(err.span.map(|span| span.column), Some(8));
+            },
+            _ => panic!("Unexpected result")
+        }
     }
-}
\ No newline at end of file
+}
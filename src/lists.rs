@@ -0,0 +1,351 @@
+use super::*;
+
+// Adds the list functions to an env
+pub(crate) fn register(env: &LisperEnv) {
+    env.register("list", LisperArity::AtLeast(0), "Creates a list of the values.", list);
+    env.register("cons", LisperArity::Exact(2), "Creates a list with the value in front of the items of the list.", cons);
+    env.register("first", LisperArity::Exact(1), "The first item of a non-empty list.", first);
+    env.register("car", LisperArity::Exact(1), "The first item of a non-empty list.", first);
+    env.register("rest", LisperArity::Exact(1), "The list without its first item.", rest);
+    env.register("cdr", LisperArity::Exact(1), "The list without its first item.", rest);
+    env.register("nth", LisperArity::Exact(2), "The item at a zero based index in the list.", nth);
+    env.register("length", LisperArity::Exact(1), "The number of items in a list.", length);
+    env.register("empty?", LisperArity::Exact(1), "True if the list has no items.", is_empty);
+    env.register("append", LisperArity::AtLeast(0), "Joins the lists together into one list.", append);
+    env.register("reverse", LisperArity::Exact(1), "The items of a list in reverse order.", reverse);
+    env.register("range", LisperArity::Range(1, 3), "A list of numbers from start (default 0) up to end, by step (default 1).", range);
+    env.register("map", LisperArity::AtLeast(2), "Calls the function with the items of the lists, and lists the results.", map);
+    env.register("filter", LisperArity::Exact(2), "The items of the list that the function is true for.", filter);
+    env.register("reduce", LisperArity::Range(2, 3), "Combines the items with (f acc item), starting from init or the first item.", reduce);
+    env.register("fold", LisperArity::Exact(3), "Combines the items with (f acc item), starting from init.", fold);
+    env.register("sort", LisperArity::Range(1, 2), "Sorts the list, by a less than function if given.", sort);
+    env.register("any?", LisperArity::Exact(2), "True if the function is true for any item in the list.", any);
+    env.register("every?", LisperArity::Exact(2), "True if the function is true for every item in the list.", every);
+}
+
+fn list(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::List(args.to_vec().into()))
+}
+
+fn cons(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("cons", &args[1], 1)?;
+    let mut res = Vec::with_capacity(items.len() + 1);
+    res.push(args[0].clone());
+    res.extend_from_slice(items);
+    Ok(LisperExp::List(res.into()))
+}
+
+fn first(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("first", &args[0], 0)?;
+    items.first().cloned().ok_or_else(|| index_error("first", 0, 0))
+}
+
+fn rest(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("rest", &args[0], 0)?;
+    Ok(LisperExp::List(items.iter().skip(1).cloned().collect()))
+}
+
+fn nth(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("nth", &args[0], 0)?;
+    let index = expect_index("nth", &args[1], 1)?;
+    items.get(index).cloned().ok_or_else(|| index_error("nth", index, items.len()))
+}
+
+fn length(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("length", &args[0], 0)?;
+    Ok(LisperExp::Int(items.len() as i64))
+}
+
+fn is_empty(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("empty?", &args[0], 0)?;
+    Ok(LisperExp::Bool(items.is_empty()))
+}
+
+fn append(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut res: Vec<LisperExp> = vec![];
+    for (i, arg) in args.iter().enumerate() {
+        res.extend_from_slice(expect_list("append", arg, i)?);
+    }
+    Ok(LisperExp::List(res.into()))
+}
+
+fn reverse(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("reverse", &args[0], 0)?;
+    Ok(LisperExp::List(items.iter().rev().cloned().collect()))
+}
+
+// The most items a range can have, so a bad bound or step fails instead of
+// trying to allocate more memory than there is
+const MAX_RANGE_LENGTH: usize = 10_000_000;
+
+fn range(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    use numbers::Num;
+
+    let mut nums: Vec<Num> = vec![];
+    for (i, arg) in args.iter().enumerate() {
+        nums.push(numbers::expect_real("range", arg, i)?);
+    }
+    let (start, end, step) = match nums.as_slice() {
+        [end] => (Num::Int(0), end.clone(), Num::Int(1)),
+        [start, end] => (start.clone(), end.clone(), Num::Int(1)),
+        [start, end, step] => (start.clone(), end.clone(), step.clone()),
+        _ => unreachable!("arity is checked before the call"),
+    };
+    let step_error = || LisperErr::new(LisperErrKind::TypeError, "range expects a finite, non-zero step.");
+    let length_error = || LisperErr::new(
+        LisperErrKind::IndexOutOfRange,
+        format!("range would have more than the maximum of {} items.", MAX_RANGE_LENGTH)
+    );
+
+    // Exact numbers give a range of exact numbers
+    if let (Some(first), Some(last), Some(step_ratio)) = (start.to_ratio(), end.to_ratio(), step.to_ratio()) {
+        if step_ratio.is_zero() {
+            return Err(step_error());
+        }
+        let count = (&(&last - &first) / &step_ratio).ceil();
+        let count = match count.to_i64() {
+            Some(n) if n <= MAX_RANGE_LENGTH as i64 => n.max(0),
+            _ if count.is_negative() => 0,
+            _ => return Err(length_error()),
+        };
+        let mut items = vec![];
+        for i in 0..count {
+            let offset = numbers::mul("range", Num::Int(i), step.clone())?;
+            items.push(numbers::add("range", start.clone(), offset)?.into());
+        }
+        return Ok(LisperExp::List(items.into()));
+    }
+
+    let (start, end, step) = (start.to_f64(), end.to_f64(), step.to_f64());
+    if step == 0.0 || !step.is_finite() {
+        return Err(step_error());
+    }
+
+    // Each item is computed from start, so float steps don't add up errors
+    let count = ((end - start) / step).ceil().max(0.0);
+    if count > MAX_RANGE_LENGTH as f64 {
+        return Err(length_error());
+    }
+    let count = count as usize;
+    Ok(LisperExp::List((0..count).map(|i| LisperExp::Number(start + i as f64 * step)).collect()))
+}
+
+fn map(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let func = &args[0];
+    let mut lists: Vec<&[LisperExp]> = vec![];
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        lists.push(expect_list("map", arg, i)?);
+    }
+
+    // With several lists, the function gets one item from each list, and
+    // stops at the end of the shortest list
+    let count = lists.iter().map(|items| items.len()).min().unwrap_or(0);
+    let mut res: Vec<LisperExp> = Vec::with_capacity(count);
+    for i in 0..count {
+        let call_args: Vec<LisperExp> = lists.iter().map(|items| items[i].clone()).collect();
+        res.push(apply(func, call_args, env)?);
+    }
+    Ok(LisperExp::List(res.into()))
+}
+
+fn filter(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("filter", &args[1], 1)?;
+    let mut res: Vec<LisperExp> = vec![];
+    for item in items.iter() {
+        if is_truthy(&apply(&args[0], vec![item.clone()], env)?) {
+            res.push(item.clone());
+        }
+    }
+    Ok(LisperExp::List(res.into()))
+}
+
+fn reduce(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    if args.len() == 3 {
+        return fold(args, env);
+    }
+
+    let items = expect_list("reduce", &args[1], 1)?;
+    let (init, rest) = items.split_first().ok_or_else(|| LisperErr::new(
+        LisperErrKind::IndexOutOfRange,
+        "reduce of an empty list needs an init value."
+    ))?;
+    fold_items(&args[0], init.clone(), rest, env)
+}
+
+fn fold(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("fold", &args[2], 2)?;
+    fold_items(&args[0], args[1].clone(), items, env)
+}
+
+fn fold_items(func: &LisperExp, init: LisperExp, items: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut acc = init;
+    for item in items.iter() {
+        acc = apply(func, vec![acc, item.clone()], env)?;
+    }
+    Ok(acc)
+}
+
+fn sort(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("sort", &args[0], 0)?.to_vec();
+
+    match args.get(1) {
+        Some(func) => merge_sort(items, &mut |a, b| Ok(is_truthy(&apply(func, vec![a.clone(), b.clone()], env)?))),
+        None => merge_sort(items, &mut |a, b| Ok(a.total_cmp(b) == Ordering::Less)),
+    }.map(|items| LisperExp::List(items.into()))
+}
+
+// A stable merge sort with a less than function that can fail. Unlike the
+// std sorts, a less than function that isn't a total order can't cause a panic.
+fn merge_sort<F>(mut items: Vec<LisperExp>, less_than: &mut F) -> Result<Vec<LisperExp>, LisperErr>
+where F: FnMut(&LisperExp, &LisperExp) -> Result<bool, LisperErr> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, less_than)?;
+    let right = merge_sort(right, less_than)?;
+
+    let mut res = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Take from the right only if it is strictly smaller, to keep the order of equal items
+        if less_than(b, a)? {
+            res.extend(right.next());
+        } else {
+            res.extend(left.next());
+        }
+    }
+    res.extend(left);
+    res.extend(right);
+    Ok(res)
+}
+
+fn any(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("any?", &args[1], 1)?;
+    for item in items.iter() {
+        if is_truthy(&apply(&args[0], vec![item.clone()], env)?) {
+            return Ok(LisperExp::Bool(true));
+        }
+    }
+    Ok(LisperExp::Bool(false))
+}
+
+fn every(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("every?", &args[1], 1)?;
+    for item in items.iter() {
+        if !is_truthy(&apply(&args[0], vec![item.clone()], env)?) {
+            return Ok(LisperExp::Bool(false));
+        }
+    }
+    Ok(LisperExp::Bool(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::eval_str;
+
+    // Evaluates an expression in a new default env and prints the result
+    fn eval_print(expr: &str) -> Result<String, Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+        Ok(eval_str(expr, env)?.to_string())
+    }
+
+    #[test]
+    fn construct() -> Result<(),  Box<dyn std::error::Error>> {
+        assert_eq!(eval_print("(list 1 (+ 1 1) \"a\")")?, "(1 2 \"a\")");
+        assert_eq!(eval_print("(list)")?, "()");
+        assert_eq!(eval_print("(cons 0 '(1 2))")?, "(0 1 2)");
+        assert_eq!(eval_print("(append '(1) '() '(2 3))")?, "(1 2 3)");
+        assert!(eval_print("(cons 1 2)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn access() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        assert_eq!(eval_print("(first '(1 2 3))")?, "1");
+        assert_eq!(eval_print("(car '(1 2 3))")?, "1");
+        assert_eq!(eval_print("(rest '(1 2 3))")?, "(2 3)");
+        assert_eq!(eval_print("(cdr '())")?, "()");
+        assert_eq!(eval_print("(nth '(a b c) 2)")?, "c");
+        assert_eq!(eval_print("(length '(a b c))")?, "3");
+        assert_eq!(eval_print("(empty? '())")?, "true");
+        assert_eq!(eval_print("(empty? '(1))")?, "false");
+        assert_eq!(eval_print("(reverse '(1 2 3))")?, "(3 2 1)");
+
+        let env = &mut create_default_env();
+        assert_eq!(eval_str("(nth '(a b c) 3)", env).map_err(|err| err.kind).err(), Some(LisperErrKind::IndexOutOfRange));
+        assert_eq!(eval_str("(first '())", env).map_err(|err| err.kind).err(), Some(LisperErrKind::IndexOutOfRange));
+        assert_eq!(eval_str("(length 5)", env).map_err(|err| err.kind).err(), Some(LisperErrKind::TypeError));
+        Ok(())
+    }
+
+    #[test]
+    fn ranges() -> Result<(),  Box<dyn std::error::Error>> {
+        assert_eq!(eval_print("(range 4)")?, "(0 1 2 3)");
+        assert_eq!(eval_print("(range 2 5)")?, "(2 3 4)");
+        assert_eq!(eval_print("(range 10 0 -3)")?, "(10 7 4 1)");
+        assert_eq!(eval_print("(range 0 1 0.25)")?, "(0.0 0.25 0.5 0.75)");
+        assert_eq!(eval_print("(range 0 1 1/4)")?, "(0 1/4 1/2 3/4)");
+        assert_eq!(eval_print("(range 5 2)")?, "()");
+        assert!(eval_print("(range 0 5 0)").is_err());
+        assert_eq!(eval_print("(range -100000000000000000000)")?, "()");
+
+        Ok(())
+    }
+
+    #[test]
+    fn range_too_long() {
+        use super::*;
+
+        // Ranges too long to build are an error, not an allocation failure
+        let env = &mut create_default_env();
+        let kind = |expr: &str, env: &mut LisperEnv| eval_str(expr, env).map_err(|err| err.kind).err();
+        assert_eq!(kind("(range 1e300)", env), Some(LisperErrKind::IndexOutOfRange));
+        assert_eq!(kind("(range 0 10 1e-300)", env), Some(LisperErrKind::IndexOutOfRange));
+        assert_eq!(kind("(range 100000000000000000000)", env), Some(LisperErrKind::IndexOutOfRange));
+        assert_eq!(kind("(range 0 1 1/100000000)", env), Some(LisperErrKind::IndexOutOfRange));
+    }
+
+    #[test]
+    fn higher_order() -> Result<(),  Box<dyn std::error::Error>> {
+        // Builtins and user defined functions both work
+        assert_eq!(eval_print("(map (fn (x) (* x x)) '(1 2 3))")?, "(1 4 9)");
+        assert_eq!(eval_print("(map + '(1 2 3) '(10 20))")?, "(11 22)");
+        assert_eq!(eval_print("(filter (fn (x) (> x 1)) '(0 1 2 3))")?, "(2 3)");
+        assert_eq!(eval_print("(reduce + '(1 2 3 4))")?, "10");
+        assert_eq!(eval_print("(reduce + 100 '(1 2 3 4))")?, "110");
+        assert_eq!(eval_print("(fold (fn (acc x) (cons x acc)) '() '(1 2 3))")?, "(3 2 1)");
+        assert_eq!(eval_print("(any? (fn (x) (> x 2)) '(1 2 3))")?, "true");
+        assert_eq!(eval_print("(any? (fn (x) (> x 5)) '(1 2 3))")?, "false");
+        assert_eq!(eval_print("(every? (fn (x) (> x 0)) '(1 2 3))")?, "true");
+        assert_eq!(eval_print("(every? (fn (x) (> x 1)) '(1 2 3))")?, "false");
+        assert!(eval_print("(reduce + '())").is_err());
+        assert!(eval_print("(map 1 '(1 2))").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sorting() -> Result<(),  Box<dyn std::error::Error>> {
+        assert_eq!(eval_print("(sort '(3 1 2))")?, "(1 2 3)");
+        assert_eq!(eval_print("(sort '(\"b\" \"c\" \"a\"))")?, "(\"a\" \"b\" \"c\")");
+        assert_eq!(eval_print("(sort '(3 1 2) >)")?, "(3 2 1)");
+
+        // Sorting is stable, items that compare equal keep their order
+        assert_eq!(
+            eval_print("(sort '((2 a) (1 b) (2 c) (1 d)) (fn (x y) (< (first x) (first y))))")?,
+            "((1 b) (1 d) (2 a) (2 c))"
+        );
+
+        // A comparator that isn't a total order doesn't crash
+        assert_eq!(eval_print("(length (sort (range 50) (fn (x y) true)))")?, "50");
+        // Mixed types are ordered by type
+        assert_eq!(eval_print("(sort '(\"a\" (2) b 1 true (1 2) (1)))")?, "(true 1 \"a\" b (1) (1 2) (2))");
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use super::*;
+
+// Adds the macro functions to an env
+pub(crate) fn register(env: &LisperEnv) {
+    env.register("macroexpand-1", LisperArity::Exact(1), "Expands a macro call once, other expressions are returned as is.", macroexpand_1);
+    env.register("macroexpand", LisperArity::Exact(1), "Expands a macro call until it is no longer a macro call.", macroexpand);
+    env.register("gensym", LisperArity::Range(0, 1), "Creates a new unique symbol, with an optional prefix.", gensym);
+}
+
+// Expands a form once if it is a call to a macro bound in the env
+fn expand_once(form: &LisperExp, env: &LisperEnv) -> Result<Option<LisperExp>, LisperErr> {
+    if let LisperExp::List(items) = form {
+        if let Some((LisperExp::Symbol(sym), args)) = items.split_first() {
+            match env.get(sym).as_ref() {
+                Some(LisperExp::Macro(mac)) => return expand_macro(mac, args).map(Some),
+                Some(LisperExp::SyntaxRules(rules)) => return syntax_rules::expand(rules, args).map(Some),
+                _ => {}
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn macroexpand_1(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(expand_once(&args[0], env)?.unwrap_or_else(|| args[0].clone()))
+}
+
+fn macroexpand(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut form = args[0].clone();
+    while let Some(expansion) = expand_once(&form, env)? {
+        form = expansion;
+    }
+    Ok(form)
+}
+
+// The symbol is named like the ones syntax-rules renames, with a # and a
+// mark from the env, which source code can't refer to
+fn gensym(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let prefix = match args.first() {
+        Some(arg) => expect_string("gensym", arg, 0)?,
+        None => "G",
+    };
+    Ok(LisperExp::Symbol(format!("{}#{}", prefix, env.next_mark())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::eval_str;
+
+    #[test]
+    fn defmacro() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        // The arguments are not evaluated, so only one branch runs
+        eval_str("(defmacro my-unless (test then else) `(if ,test ,else ,then))", env)?;
+        assert_eq!(eval_str("(my-unless false 1 (error \"not reached\"))", env)?.to_string(), "1");
+        assert_eq!(eval_str("(my-unless true (error \"not reached\") 2)", env)?.to_string(), "2");
+        assert_eq!(eval_str("my-unless", env)?.to_string(), "#<macro my-unless>");
+
+        // Macros can define things in the scope they are called in
+        eval_str("(defmacro defconst (name value) `(def ,name ,value))", env)?;
+        eval_str("(defconst answer (* 6 7))", env)?;
+        assert_eq!(eval_str("answer", env)?.to_string(), "42");
+        Ok(())
+    }
+
+    #[test]
+    fn threading_macro() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str(
+            "(defmacro -> (x & forms)
+                (if (empty? forms)
+                    x
+                    `(-> (,(first (first forms)) ,x ,@(rest (first forms))) ,@(rest forms))))",
+            env
+        )?;
+        assert_eq!(eval_str("(-> 5 (- 1) (* 2) (list 0))", env)?.to_string(), "(8 0)");
+        assert_eq!(eval_str("(-> 5)", env)?.to_string(), "5");
+        Ok(())
+    }
+
+    #[test]
+    fn macroexpand() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(defmacro my-when (test & body) `(if ,test (my-do ,@body) ()))", env)?;
+        eval_str("(defmacro my-do (& body) `((fn () ,@body)))", env)?;
+        assert_eq!(eval_str("(macroexpand-1 '(my-when x 1 2))", env)?.to_string(), "(if x (my-do 1 2) ())");
+        assert_eq!(eval_str("(macroexpand '(my-do 1 2))", env)?.to_string(), "((fn () 1 2))");
+        assert_eq!(eval_str("(macroexpand '(+ 1 2))", env)?.to_string(), "(+ 1 2)");
+        assert_eq!(eval_str("(macroexpand 3)", env)?.to_string(), "3");
+        assert_eq!(eval_str("(my-when true 1 2)", env)?.to_string(), "2");
+        Ok(())
+    }
+
+    #[test]
+    fn gensym() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        match (eval_str("(gensym)", env)?, eval_str("(gensym \"tmp\")", env)?) {
+            (LisperExp::Symbol(a), LisperExp::Symbol(b)) => {
+                assert!(a.starts_with("G#"));
+                assert!(b.starts_with("tmp#"));
+                assert_ne!(a, eval_str("(gensym)", env)?.to_string());
+
+                // Source can't write the same name
+                match eval_str(&format!("'{}", a), env) {
+                    Err(err) => assert_eq!(err.kind, LisperErrKind::ParseError),
+                    _ => panic!("Unexpected result")
+                }
+            },
+            _ => panic!("Unexpected result")
+        }
+
+        // The value is only evaluated once, and the fresh name cannot
+        // capture a variable of the caller
+        eval_str(
+            "(defmacro my-or (a b)
+                (let ((tmp (gensym)))
+                    `(let ((,tmp ,a)) (if ,tmp ,tmp ,b))))",
+            env
+        )?;
+        eval_str("(def calls 0)", env)?;
+        eval_str("(defn next () (set! calls (+ calls 1)))", env)?;
+        assert_eq!(eval_str("(my-or (next) 5)", env)?.to_string(), "1");
+        assert_eq!(eval_str("calls", env)?.to_string(), "1");
+        assert_eq!(eval_str("(let ((tmp false)) (my-or tmp 5))", env)?.to_string(), "5");
+        Ok(())
+    }
+
+    #[test]
+    fn macro_errors() {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str("(defmacro two (a b) a)", env).unwrap();
+        match eval_str("(two 1)", env) {
+            Err(err) => assert_eq!(err.message, "two expects 2 arguments, got 1."),
+            _ => panic!("Unexpected result")
+        }
+        assert!(eval_str("(defmacro 1 (a) a)", env).is_err());
+        assert!(eval_str("(defmacro bad (a &) a)", env).is_err());
+        assert!(eval_str("(gensym 1)", env).is_err());
+    }
+}
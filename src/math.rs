@@ -0,0 +1,316 @@
+use super::*;
+use numbers::Num;
+
+// Adds the math constants and functions to an env
+pub(crate) fn register(env: &LisperEnv) {
+    env.define("e", LisperExp::Number(std::f64::consts::E));
+    env.define("two_pi", LisperExp::Number(std::f64::consts::PI * 2.0));
+    env.define("tau", LisperExp::Number(std::f64::consts::TAU));
+
+    register_complex(env, "exp", "e raised to the power of the number.", f64::exp, Complex::exp);
+    register_rounding(env, "floor", "The largest whole number less than or equal to the number.", f64::floor, Rational::floor);
+    register_rounding(env, "ceil", "The smallest whole number greater than or equal to the number.", f64::ceil, Rational::ceil);
+    register_rounding(env, "round", "The nearest whole number, halfway cases round away from zero.", f64::round, Rational::round);
+    register_rounding(env, "trunc", "The whole part of the number, rounding towards zero.", f64::trunc, Rational::trunc);
+    register_unary(env, "asin", "The arcsine of the number, in radians.", f64::asin);
+    register_unary(env, "acos", "The arccosine of the number, in radians.", f64::acos);
+    register_complex(env, "sinh", "The hyperbolic sine of the number.", f64::sinh, Complex::sinh);
+    register_complex(env, "cosh", "The hyperbolic cosine of the number.", f64::cosh, Complex::cosh);
+    register_complex(env, "tanh", "The hyperbolic tangent of the number.", f64::tanh, Complex::tanh);
+
+    env.register("sqrt", LisperArity::Exact(1), "The square root of the number, negative numbers have an imaginary root.", sqrt);
+    env.register("abs", LisperArity::Exact(1), "The absolute value of the number.", abs);
+    env.register("pow", LisperArity::Exact(2), "The first number raised to the power of the second.", pow);
+    env.register("expt", LisperArity::Exact(2), "The first number raised to the power of the second.", pow);
+    env.register("log", LisperArity::Range(1, 2), "The logarithm of the number, natural or to the given base.", log);
+    env.register("min", LisperArity::AtLeast(1), "The smallest of the numbers.", min);
+    env.register("max", LisperArity::AtLeast(1), "The largest of the numbers.", max);
+    env.register("atan", LisperArity::Range(1, 2), "The arctangent of y, or of y/x using the signs to find the quadrant.", atan);
+    env.register("atan2", LisperArity::Exact(2), "The arctangent of y/x, using the signs to find the quadrant.", atan2);
+    env.register("hypot", LisperArity::Exact(2), "The length of the hypotenuse of a right triangle with sides x and y.", hypot);
+    env.register("clamp", LisperArity::Exact(3), "The number limited to the range from min to max.", clamp);
+    env.register("lerp", LisperArity::Exact(3), "Interpolates linearly from a to b, by t from 0 to 1.", lerp);
+}
+
+// Registers a function of one number
+fn register_unary(env: &LisperEnv, name: &'static str, doc: &str, func: fn(f64) -> f64) {
+    env.register(name, LisperArity::Exact(1), doc, move |args, _env| {
+        Ok(LisperExp::Number(func(expect_number(name, &args[0], 0)?)))
+    });
+}
+
+// Registers a function of one number that also works on complex numbers
+fn register_complex(env: &LisperEnv, name: &'static str, doc: &str, func: fn(f64) -> f64, complex: fn(Complex) -> Complex) {
+    env.register(name, LisperArity::Exact(1), doc, move |args, _env| {
+        numbers::unary(name, &args[0], func, complex)
+    });
+}
+
+// Registers a function that rounds a number to a whole number. Fractions
+// round to an exact integer, and integers are returned as they are.
+fn register_rounding(env: &LisperEnv, name: &'static str, doc: &str, func: fn(f64) -> f64, exact: fn(&Rational) -> BigInt) {
+    env.register(name, LisperArity::Exact(1), doc, move |args, _env| {
+        match numbers::expect_real(name, &args[0], 0)? {
+            Num::Float(n) => Ok(LisperExp::Number(func(n))),
+            Num::Ratio(n) => Ok(Num::from_big(exact(&n)).into()),
+            whole => Ok(whole.into()),
+        }
+    });
+}
+
+// Checks that all the arguments are numbers
+fn expect_numbers(func: &str, args: &[LisperExp]) -> Result<Vec<f64>, LisperErr> {
+    args.iter().enumerate().map(|(i, arg)| expect_number(func, arg, i)).collect()
+}
+
+// The values of the arguments if they are all exact numbers
+fn expect_exact(args: &[LisperExp]) -> Option<Vec<Num>> {
+    args.iter().map(|arg| Num::from_exp(arg).filter(Num::is_exact)).collect()
+}
+
+// Picks the number that compares as wanted to all the others
+fn pick(nums: Vec<Num>, wanted: Ordering) -> LisperExp {
+    let mut nums = nums.into_iter();
+    let first = nums.next().expect("arity is checked before the call");
+    nums.fold(first, |best, n| if numbers::compare(&n, &best) == Some(wanted) { n } else { best }).into()
+}
+
+fn abs(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match numbers::expect_num("abs", &args[0], 0)? {
+        Num::Int(n) => Ok(Num::from_big(BigInt::from(n).abs()).into()),
+        Num::Big(n) => Ok(LisperExp::BigInt(n.abs())),
+        Num::Ratio(n) => Ok(LisperExp::Rational(Rc::new(n.abs()))),
+        Num::Float(n) => Ok(LisperExp::Number(n.abs())),
+        Num::Complex(n) => Ok(LisperExp::Number(n.norm())),
+    }
+}
+
+fn sqrt(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match numbers::expect_num("sqrt", &args[0], 0)? {
+        n if matches!(n, Num::Complex(_)) || n.to_f64() < 0.0 => Ok(Num::from_complex(n.to_complex().sqrt()).into()),
+        n => Ok(LisperExp::Number(n.to_f64().sqrt())),
+    }
+}
+
+// The largest number of bits an exact power may have, larger powers would
+// take too long to compute
+const MAX_POW_BITS: u128 = 1 << 20;
+
+// An exact number raised to an integer power stays exact, and a complex
+// number raised to an integer power is found by multiplying
+fn pow(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let base = numbers::expect_num("pow", &args[0], 0)?;
+    let exponent = numbers::expect_num("pow", &args[1], 1)?;
+    if let (Some(b), Num::Int(e)) = (base.to_ratio(), &exponent) {
+        if b.is_zero() && *e < 0 {
+            return Err(LisperErr::new(LisperErrKind::DivisionByZero, "pow cannot divide by zero."));
+        }
+        // Only 0, 1 and -1 have a single bit, and stay small for any power
+        let bits = b.numer().bits().max(b.denom().bits());
+        if bits > 1 && bits as u128 * e.unsigned_abs() as u128 > MAX_POW_BITS {
+            return Err(numbers::overflow_error("pow"));
+        }
+        return Ok(Num::from_ratio(b.pow(*e)).into());
+    }
+    match (&base, &exponent) {
+        (Num::Complex(b), Num::Int(e)) => return Ok(Num::from_complex(b.powi(*e)).into()),
+        (Num::Complex(_), _) | (_, Num::Complex(_)) => {
+            return Ok(Num::from_complex(base.to_complex().powc(exponent.to_complex())).into());
+        },
+        _ => {},
+    }
+    Ok(LisperExp::Number(base.to_f64().powf(exponent.to_f64())))
+}
+
+// The logarithm of a complex or negative number is the principal one
+fn log(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let n = numbers::expect_num("log", &args[0], 0)?;
+    let base = args.get(1).map(|base| numbers::expect_num("log", base, 1)).transpose()?;
+    let is_complex = |n: &Num| matches!(n, Num::Complex(_)) || n.to_f64() < 0.0;
+    if is_complex(&n) || base.as_ref().is_some_and(is_complex) {
+        let ln = n.to_complex().ln();
+        return Ok(Num::from_complex(base.map_or(ln, |base| ln / base.to_complex().ln())).into());
+    }
+    let n = n.to_f64();
+    match base {
+        Some(base) => Ok(LisperExp::Number(n.log(base.to_f64()))),
+        None => Ok(LisperExp::Number(n.ln())),
+    }
+}
+
+// Exact numbers give an exact result, with any float the result is a float.
+// NaN is not ordered, so any NaN makes the result NaN.
+fn min(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match expect_exact(args) {
+        Some(nums) => Ok(pick(nums, Ordering::Less)),
+        None => {
+            let numbers = expect_numbers("min", args)?;
+            Ok(LisperExp::Number(numbers.into_iter().fold(f64::INFINITY, |a, b| if a.is_nan() || b.is_nan() { f64::NAN } else { a.min(b) })))
+        },
+    }
+}
+
+fn max(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match expect_exact(args) {
+        Some(nums) => Ok(pick(nums, Ordering::Greater)),
+        None => {
+            let numbers = expect_numbers("max", args)?;
+            Ok(LisperExp::Number(numbers.into_iter().fold(f64::NEG_INFINITY, |a, b| if a.is_nan() || b.is_nan() { f64::NAN } else { a.max(b) })))
+        },
+    }
+}
+
+fn atan(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    if args.len() == 2 {
+        return atan2(args, env);
+    }
+    let n = expect_number("atan", &args[0], 0)?;
+    Ok(LisperExp::Number(n.atan()))
+}
+
+fn atan2(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let y = expect_number("atan2", &args[0], 0)?;
+    let x = expect_number("atan2", &args[1], 1)?;
+    Ok(LisperExp::Number(y.atan2(x)))
+}
+
+fn hypot(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let x = expect_number("hypot", &args[0], 0)?;
+    let y = expect_number("hypot", &args[1], 1)?;
+    Ok(LisperExp::Number(x.hypot(y)))
+}
+
+fn clamp(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    if let Some(nums) = expect_exact(args) {
+        let (low, high) = (&args[1], &args[2]);
+        if numbers::compare(&nums[1], &nums[2]) == Some(Ordering::Greater) {
+            return Err(clamp_error(low, high));
+        }
+        return Ok(match (numbers::compare(&nums[0], &nums[1]), numbers::compare(&nums[0], &nums[2])) {
+            (Some(Ordering::Less), _) => low.clone(),
+            (_, Some(Ordering::Greater)) => high.clone(),
+            _ => args[0].clone(),
+        });
+    }
+    let numbers = expect_numbers("clamp", args)?;
+    let (n, low, high) = (numbers[0], numbers[1], numbers[2]);
+    // f64::clamp panics on a bad range, which includes NaN bounds
+    if low.is_nan() || high.is_nan() || low > high {
+        return Err(clamp_error(low, high));
+    }
+    Ok(LisperExp::Number(n.clamp(low, high)))
+}
+
+fn clamp_error(low: impl fmt::Display, high: impl fmt::Display) -> LisperErr {
+    LisperErr::new(
+        LisperErrKind::IndexOutOfRange,
+        format!("clamp expects min to be at most max, got {} and {}.", low, high)
+    )
+}
+
+fn lerp(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let numbers = expect_numbers("lerp", args)?;
+    let (a, b, t) = (numbers[0], numbers[1], numbers[2]);
+    Ok(LisperExp::Number(a + (b - a) * t))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::eval_str;
+
+    // Evaluates an expression in a new default env and returns the number
+    fn eval_number(expr: &str) -> Result<f64, Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+        match eval_str(expr, env)? {
+            LisperExp::Int(n) => Ok(n as f64),
+            LisperExp::Rational(n) => Ok(n.to_f64()),
+            LisperExp::Number(n) => Ok(n),
+            other => panic!("Unexpected result {}", other)
+        }
+    }
+
+    #[test]
+    fn constants() -> Result<(),  Box<dyn std::error::Error>> {
+        assert_eq!(eval_number("e")?, std::f64::consts::E);
+        assert_eq!(eval_number("two_pi")?, eval_number("tau")?);
+        assert_eq!(eval_number("(* 2 pi)")?, eval_number("two_pi")?);
+        assert!(eval_number("inf")?.is_infinite());
+        assert!(eval_number("nan")?.is_nan());
+        Ok(())
+    }
+
+    #[test]
+    fn functions() -> Result<(),  Box<dyn std::error::Error>> {
+        assert_eq!(eval_number("(sqrt 16)")?, 4.0);
+        assert_eq!(eval_number("(pow 2 10)")?, 1024.0);
+        assert_eq!(eval_number("(expt 9 0.5)")?, 3.0);
+        assert_eq!(eval_number("(exp 0)")?, 1.0);
+        assert_eq!(eval_number("(log e)")?, 1.0);
+        assert_eq!(eval_number("(log 8 2)")?, 3.0);
+        assert_eq!(eval_number("(abs -3)")?, 3.0);
+        assert_eq!(eval_number("(floor -1.5)")?, -2.0);
+        assert_eq!(eval_number("(ceil 1.2)")?, 2.0);
+        assert_eq!(eval_number("(round 2.5)")?, 3.0);
+        assert_eq!(eval_number("(trunc -1.7)")?, -1.0);
+        assert_eq!(eval_number("(min 3 1 2)")?, 1.0);
+        assert_eq!(eval_number("(max 3 1 2)")?, 3.0);
+        assert_eq!(eval_number("(asin 1)")?, std::f64::consts::FRAC_PI_2);
+        assert_eq!(eval_number("(acos 1)")?, 0.0);
+        assert_eq!(eval_number("(atan 1)")?, std::f64::consts::FRAC_PI_4);
+        assert_eq!(eval_number("(atan 1 -1)")?, eval_number("(atan2 1 -1)")?);
+        assert_eq!(eval_number("(atan2 1 -1)")?, 3.0 * std::f64::consts::FRAC_PI_4);
+        assert_eq!(eval_number("(sinh 0)")?, 0.0);
+        assert_eq!(eval_number("(cosh 0)")?, 1.0);
+        assert_eq!(eval_number("(tanh 0)")?, 0.0);
+        assert_eq!(eval_number("(hypot 3 4)")?, 5.0);
+        assert_eq!(eval_number("(clamp 5 0 1)")?, 1.0);
+        assert_eq!(eval_number("(clamp -5 0 1)")?, 0.0);
+        assert_eq!(eval_number("(lerp 10 20 0.25)")?, 12.5);
+        Ok(())
+    }
+
+    #[test]
+    fn integer_results() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert_eq!(eval_str("(abs -3)", env)?, LisperExp::Int(3));
+        assert_eq!(eval_str("(pow 2 62)", env)?, LisperExp::Int(1 << 62));
+        assert_eq!(eval_str("(pow 2 -1)", env)?.to_string(), "1/2");
+        assert_eq!(eval_str("(pow 2 0.5)", env)?, LisperExp::Number(std::f64::consts::SQRT_2));
+        assert_eq!(eval_str("(floor 7)", env)?, LisperExp::Int(7));
+        assert_eq!(eval_str("(max 1 3 2)", env)?, LisperExp::Int(3));
+        assert_eq!(eval_str("(max 1 3.0 2)", env)?, LisperExp::Number(3.0));
+        // Any NaN makes min and max NaN, wherever it is
+        assert!(eval_number("(min nan 1)")?.is_nan());
+        assert!(eval_number("(min 1 nan)")?.is_nan());
+        assert!(eval_number("(max 1 nan 2.0)")?.is_nan());
+        assert_eq!(eval_str("(clamp 5 0 1)", env)?, LisperExp::Int(1));
+        assert_eq!(eval_str("(sqrt 4)", env)?, LisperExp::Number(2.0));
+        Ok(())
+    }
+
+    #[test]
+    fn math_errors() {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        match eval_str("(sqrt \"4\")", env) {
+            Err(err) => assert_eq!(err.message, "sqrt expects a number as argument 1, got string \"4\"."),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(pow 2)", env) {
+            Err(err) => assert_eq!(err.kind, LisperErrKind::ArityError),
+            _ => panic!("Unexpected result")
+        }
+        assert!(eval_str("(max 1 true)", env).is_err());
+        assert!(eval_str("(log 1 2 3)", env).is_err());
+        assert_eq!(eval_str("(clamp 1 2 0)", env).map_err(|err| err.kind).err(), Some(LisperErrKind::IndexOutOfRange));
+        assert_eq!(eval_str("(clamp 1/2 1 0)", env).map_err(|err| err.kind).err(), Some(LisperErrKind::IndexOutOfRange));
+        assert!(eval_str("(clamp 1 nan 2)", env).is_err());
+    }
+}
@@ -0,0 +1,743 @@
+use super::*;
+
+// Adds the integer, fraction and complex functions, and the conversions
+// between exact numbers and floats
+pub(crate) fn register(env: &LisperEnv) {
+    env.register("quot", LisperArity::Exact(2), "The quotient of dividing two whole numbers, rounded towards zero.", quot);
+    env.register("rem", LisperArity::Exact(2), "The remainder of dividing two whole numbers, with the sign of the first.", rem);
+    env.register("numerator", LisperArity::Exact(1), "The numerator of the number as a fraction in lowest terms.", numerator);
+    env.register("denominator", LisperArity::Exact(1), "The denominator of the number as a fraction in lowest terms.", denominator);
+    env.register("rationalize", LisperArity::Exact(2), "The simplest fraction that differs from x by at most y.", rationalize);
+    env.register("exact->inexact", LisperArity::Exact(1), "The number as a float.", exact_to_inexact);
+    env.register("inexact->exact", LisperArity::Exact(1), "The exact value of the number, an integer or a fraction.", inexact_to_exact);
+    env.register("make-rectangular", LisperArity::Exact(2), "The complex number with the real part x and imaginary part y.", make_rectangular);
+    env.register("make-polar", LisperArity::Exact(2), "The complex number with the given magnitude and angle in radians.", make_polar);
+    env.register("real-part", LisperArity::Exact(1), "The real part of the number.", real_part);
+    env.register("imag-part", LisperArity::Exact(1), "The imaginary part of the number.", imag_part);
+    env.register("magnitude", LisperArity::Exact(1), "The distance of the number from zero.", magnitude);
+    env.register("angle", LisperArity::Exact(1), "The angle of the number from the positive real axis, in radians.", angle);
+    env.register("integer?", LisperArity::Exact(1), "True if the value is an integer.", is_integer);
+    env.register("float?", LisperArity::Exact(1), "True if the value is a float.", is_float);
+}
+
+// A number value. Integers and fractions are exact, they stay exact until
+// they are combined with a float, then the result is a float. Combining with
+// a complex number gives a complex number. Integers that fit in an i64 are
+// always an Int, fractions are never whole and complex numbers always have
+// an imaginary part.
+#[derive(Clone, Debug)]
+pub(crate) enum Num {
+    Int(i64),
+    Big(BigInt),
+    Ratio(Rational),
+    Float(f64),
+    Complex(Complex)
+}
+
+impl Num {
+    pub(crate) fn from_exp(exp: &LisperExp) -> Option<Num> {
+        match exp {
+            LisperExp::Int(n) => Some(Num::Int(*n)),
+            LisperExp::BigInt(n) => Some(Num::Big(n.clone())),
+            LisperExp::Rational(n) => Some(Num::Ratio((**n).clone())),
+            LisperExp::Number(n) => Some(Num::Float(*n)),
+            LisperExp::Complex(n) => Some(Num::Complex(*n)),
+            _ => None,
+        }
+    }
+
+    // The number for an integer, an Int if it fits
+    pub(crate) fn from_big(n: BigInt) -> Num {
+        match n.to_i64() {
+            Some(n) => Num::Int(n),
+            None => Num::Big(n),
+        }
+    }
+
+    // The number for a fraction, an integer if it is whole
+    pub(crate) fn from_ratio(n: Rational) -> Num {
+        if n.is_integer() {
+            Num::from_big(n.numer().clone())
+        } else {
+            Num::Ratio(n)
+        }
+    }
+
+    // The number for a complex value, a float if it is real
+    pub(crate) fn from_complex(n: Complex) -> Num {
+        if n.im == 0.0 {
+            Num::Float(n.re)
+        } else {
+            Num::Complex(n)
+        }
+    }
+
+    // The value as a float, the real part of a complex number
+    pub(crate) fn to_f64(&self) -> f64 {
+        match self {
+            Num::Int(n) => *n as f64,
+            Num::Big(n) => n.to_f64(),
+            Num::Ratio(n) => n.to_f64(),
+            Num::Float(n) => *n,
+            Num::Complex(n) => n.re,
+        }
+    }
+
+    pub(crate) fn to_complex(&self) -> Complex {
+        match self {
+            Num::Complex(n) => *n,
+            real => Complex::new(real.to_f64(), 0.0),
+        }
+    }
+
+    // The value of an integer, None for fractions and floats
+    pub(crate) fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Num::Int(n) => Some(BigInt::from(*n)),
+            Num::Big(n) => Some(n.clone()),
+            _ => None,
+        }
+    }
+
+    // The value of an exact number, None for floats
+    pub(crate) fn to_ratio(&self) -> Option<Rational> {
+        match self {
+            Num::Ratio(n) => Some(n.clone()),
+            Num::Float(_) | Num::Complex(_) => None,
+            other => other.to_big().map(Rational::from),
+        }
+    }
+
+    pub(crate) fn is_exact(&self) -> bool {
+        !matches!(self, Num::Float(_) | Num::Complex(_))
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Num::Int(n) => *n == 0,
+            Num::Float(n) => *n == 0.0,
+            // Only zero itself is stored as an Int
+            Num::Big(_) | Num::Ratio(_) | Num::Complex(_) => false,
+        }
+    }
+}
+
+impl From<Num> for LisperExp {
+    fn from(num: Num) -> LisperExp {
+        match num {
+            Num::Int(n) => LisperExp::Int(n),
+            Num::Big(n) => LisperExp::BigInt(n),
+            Num::Ratio(n) => LisperExp::Rational(Rc::new(n)),
+            Num::Float(n) => LisperExp::Number(n),
+            Num::Complex(n) => LisperExp::Complex(n),
+        }
+    }
+}
+
+// Reads a number literal, an integer of any size, a fraction like 1/3, a
+// float or a complex number like 3+4i. Returns None if the token is not a
+// number.
+pub(crate) fn parse_number(token: &str) -> Option<Result<LisperExp, LisperErr>> {
+    if let Ok(n) = token.parse::<i64>() {
+        return Some(Ok(LisperExp::Int(n)));
+    }
+    if let Some(n) = BigInt::parse(token) {
+        return Some(Ok(Num::from_big(n).into()));
+    }
+    if let Some((numer, denom)) = token.split_once('/') {
+        // Only the numerator has a sign
+        let unsigned = denom.starts_with(|c: char| c.is_ascii_digit());
+        if let (Some(numer), Some(denom), true) = (BigInt::parse(numer), BigInt::parse(denom), unsigned) {
+            if denom.is_zero() {
+                return Some(Err(LisperErr::new(LisperErrKind::ParseError, format!("{} has a zero denominator.", token))));
+            }
+            return Some(Ok(Num::from_ratio(Rational::new(numer, denom)).into()));
+        }
+    }
+    if let Some(n) = parse_complex(token) {
+        return Some(Ok(Num::from_complex(n).into()));
+    }
+    let n = match token {
+        "inf" | "+inf" => f64::INFINITY,
+        "-inf" => f64::NEG_INFINITY,
+        "nan" => f64::NAN,
+        // f64 parsing also takes words like infinity or NaN, which are left
+        // to be symbols
+        _ if token.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b)) => token.parse().ok()?,
+        _ => return None,
+    };
+    Some(Ok(LisperExp::Number(n)))
+}
+
+// Reads a complex literal, the real part is optional and the imaginary part
+// can be just a sign, like 3+4i, -2.5i or 1-i. Parts can be inf or nan, like
+// 1+infi, as long as the imaginary part has a sign.
+fn parse_complex(token: &str) -> Option<Complex> {
+    let body = token.strip_suffix('i')?;
+    // The imaginary part starts at the last sign that isn't in an exponent
+    let split = body.char_indices()
+        .rev()
+        .find(|(i, c)| matches!(c, '+' | '-') && !body[..*i].ends_with(['e', 'E']))
+        .map_or(0, |(i, _)| i);
+    let (re, im) = body.split_at(split);
+    // Only digits and the like, so symbols such as nani are not numbers
+    let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b));
+    let is_word = |part: &str| ["inf", "nan"].iter().any(|word| part.trim_start_matches(['+', '-']) == *word);
+    if !(is_digits(re) || is_word(re)) || !(is_digits(im) || (im.starts_with(['+', '-']) && is_word(im))) {
+        return None;
+    }
+    let re = if re.is_empty() { 0.0 } else { re.parse().ok()? };
+    let im = match im {
+        "+" => 1.0,
+        "-" => -1.0,
+        _ => im.parse().ok()?,
+    };
+    Some(Complex::new(re, im))
+}
+
+// Prints a float with the fewest digits that read back as the same float.
+// Very large and very small magnitudes get an exponent, like 1.5e300, rather
+// than hundreds of digits.
+pub(crate) fn format_float(n: f64) -> String {
+    let magnitude = n.abs();
+    if n.is_nan() {
+        // Written the way it is read
+        "nan".to_string()
+    } else if n.is_finite() && magnitude != 0.0 && !(1e-4..1e16).contains(&magnitude) {
+        format!("{:e}", n)
+    } else {
+        n.to_string()
+    }
+}
+
+// Returns the value of a number argument, or a type error
+pub(crate) fn expect_num(func: &str, arg: &LisperExp, pos: usize) -> Result<Num, LisperErr> {
+    Num::from_exp(arg).ok_or_else(|| type_error(func, pos, "number", arg))
+}
+
+// Returns the value of a number argument that is not complex
+pub(crate) fn expect_real(func: &str, arg: &LisperExp, pos: usize) -> Result<Num, LisperErr> {
+    match expect_num(func, arg, pos)? {
+        Num::Complex(_) => Err(type_error(func, pos, "real number", arg)),
+        num => Ok(num),
+    }
+}
+
+// Applies a function of one number, real numbers give a real result
+pub(crate) fn unary(func: &str, arg: &LisperExp, real: fn(f64) -> f64, complex: fn(Complex) -> Complex) -> Result<LisperExp, LisperErr> {
+    match expect_num(func, arg, 0)? {
+        Num::Complex(n) => Ok(Num::from_complex(complex(n)).into()),
+        n => Ok(LisperExp::Number(real(n.to_f64()))),
+    }
+}
+
+// Applies the operation to the first number and each of the rest in turn
+pub(crate) fn fold(func: &str, args: &[LisperExp], op: fn(&str, Num, Num) -> Result<Num, LisperErr>) -> Result<LisperExp, LisperErr> {
+    let mut acc = Num::Int(0);
+    for (i, arg) in args.iter().enumerate() {
+        let n = expect_num(func, arg, i)?;
+        acc = if i == 0 { n } else { op(func, acc, n)? };
+    }
+    Ok(acc.into())
+}
+
+// An arithmetic operation for each kind of number. The i64 version returns
+// None when the result doesn't fit, then it is done again with big integers.
+struct Arith {
+    int: fn(i64, i64) -> Option<i64>,
+    big: fn(&BigInt, &BigInt) -> BigInt,
+    ratio: fn(&Rational, &Rational) -> Rational,
+    float: fn(f64, f64) -> f64
+}
+
+const ADD: Arith = Arith { int: i64::checked_add, big: |x, y| x + y, ratio: |x, y| x + y, float: |x, y| x + y };
+const SUB: Arith = Arith { int: i64::checked_sub, big: |x, y| x - y, ratio: |x, y| x - y, float: |x, y| x - y };
+const MUL: Arith = Arith { int: i64::checked_mul, big: |x, y| x * y, ratio: |x, y| x * y, float: |x, y| x * y };
+const REM: Arith = Arith {
+    int: i64::checked_rem,
+    big: |x, y| x.div_rem(y).1,
+    ratio: |x, y| x - &(y * &Rational::from((x / y).trunc())),
+    float: |x, y| x % y
+};
+const QUOT: Arith = Arith {
+    int: i64::checked_div,
+    big: |x, y| x.div_rem(y).0,
+    ratio: |x, y| Rational::from((x / y).trunc()),
+    float: |x, y| (x / y).trunc()
+};
+
+// Combines two numbers with the most exact kind of number that holds both
+fn combine(a: &Num, b: &Num, op: &Arith) -> Num {
+    if let (Num::Int(x), Num::Int(y)) = (a, b) {
+        if let Some(n) = (op.int)(*x, *y) {
+            return Num::Int(n);
+        }
+    }
+    if let (Some(x), Some(y)) = (a.to_big(), b.to_big()) {
+        return Num::from_big((op.big)(&x, &y));
+    }
+    if let (Some(x), Some(y)) = (a.to_ratio(), b.to_ratio()) {
+        return Num::from_ratio((op.ratio)(&x, &y));
+    }
+    Num::Float((op.float)(a.to_f64(), b.to_f64()))
+}
+
+// Combines two numbers as complex numbers, if either of them is complex
+fn combine_complex(a: &Num, b: &Num, op: fn(Complex, Complex) -> Complex) -> Option<Num> {
+    match (a, b) {
+        (Num::Complex(_), _) | (_, Num::Complex(_)) => Some(Num::from_complex(op(a.to_complex(), b.to_complex()))),
+        _ => None,
+    }
+}
+
+pub(crate) fn add(_func: &str, a: Num, b: Num) -> Result<Num, LisperErr> {
+    Ok(combine_complex(&a, &b, |x, y| x + y).unwrap_or_else(|| combine(&a, &b, &ADD)))
+}
+
+pub(crate) fn sub(_func: &str, a: Num, b: Num) -> Result<Num, LisperErr> {
+    Ok(combine_complex(&a, &b, |x, y| x - y).unwrap_or_else(|| combine(&a, &b, &SUB)))
+}
+
+// Flips the sign of a number. Floats are negated directly rather than
+// subtracted from 0, so (- 0.0) is -0.0.
+pub(crate) fn negate(func: &str, n: Num) -> Result<Num, LisperErr> {
+    match n {
+        Num::Float(x) => Ok(Num::Float(-x)),
+        Num::Complex(z) => Ok(Num::Complex(Complex::new(-z.re, -z.im))),
+        exact => sub(func, Num::Int(0), exact),
+    }
+}
+
+pub(crate) fn mul(_func: &str, a: Num, b: Num) -> Result<Num, LisperErr> {
+    Ok(combine_complex(&a, &b, |x, y| x * y).unwrap_or_else(|| combine(&a, &b, &MUL)))
+}
+
+// Dividing exact numbers gives an exact result, a fraction if the division
+// is uneven
+pub(crate) fn div(func: &str, a: Num, b: Num) -> Result<Num, LisperErr> {
+    check_divisor(func, &b)?;
+    if let Some(n) = combine_complex(&a, &b, |x, y| x / y) {
+        return Ok(n);
+    }
+    if let (Num::Int(x), Num::Int(y)) = (&a, &b) {
+        if x.checked_rem(*y) == Some(0) {
+            return Ok(Num::Int(x / y));
+        }
+    }
+    match (a.to_ratio(), b.to_ratio()) {
+        (Some(x), Some(y)) => Ok(Num::from_ratio(&x / &y)),
+        _ => Ok(Num::Float(a.to_f64() / b.to_f64())),
+    }
+}
+
+pub(crate) fn modulus(func: &str, a: Num, b: Num) -> Result<Num, LisperErr> {
+    if let Some(n) = [&a, &b].iter().find(|n| matches!(n, Num::Complex(_))) {
+        return Err(LisperErr::new(
+            LisperErrKind::TypeError,
+            format!("{} expects real numbers, got {}.", func, LisperExp::from((*n).clone()))
+        ));
+    }
+    check_divisor(func, &b)?;
+    Ok(combine(&a, &b, &REM))
+}
+
+// A number as an exact value, with the infinities beyond every value
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Extended {
+    NegInfinity,
+    Value(Rational),
+    Infinity
+}
+
+// None for NaN and complex numbers
+fn extended(n: &Num) -> Option<Extended> {
+    match n {
+        Num::Float(f) if f.is_nan() => None,
+        Num::Float(f) if *f == f64::INFINITY => Some(Extended::Infinity),
+        Num::Float(f) if *f == f64::NEG_INFINITY => Some(Extended::NegInfinity),
+        Num::Float(f) => Rational::from_f64(*f).map(Extended::Value),
+        Num::Complex(_) => None,
+        exact => exact.to_ratio().map(Extended::Value),
+    }
+}
+
+// Compares two numbers by value. Exact numbers are compared to floats
+// exactly, without rounding them to a float first. NaN and complex numbers
+// are unordered.
+pub(crate) fn compare(a: &Num, b: &Num) -> Option<Ordering> {
+    match (a, b) {
+        (Num::Int(x), Num::Int(y)) => Some(x.cmp(y)),
+        (Num::Float(x), Num::Float(y)) => x.partial_cmp(y),
+        _ => Some(extended(a)?.cmp(&extended(b)?)),
+    }
+}
+
+// Whether two numbers have the same value. Complex numbers are equal when
+// both their parts are.
+pub(crate) fn equal(a: &Num, b: &Num) -> bool {
+    match (a, b) {
+        (Num::Complex(x), Num::Complex(y)) => x == y,
+        _ => compare(a, b) == Some(Ordering::Equal),
+    }
+}
+
+// A total order of numbers. NaN is ordered as by f64::total_cmp, and an
+// exact number comes before a float with the same value. Complex numbers
+// are ordered by their real part, then by their imaginary part.
+pub(crate) fn total_cmp(a: &Num, b: &Num) -> Ordering {
+    match (a, b) {
+        (Num::Float(x), Num::Float(y)) => x.total_cmp(y),
+        (Num::Complex(_), _) | (_, Num::Complex(_)) => {
+            let (x, y) = (a.to_complex(), b.to_complex());
+            x.re.total_cmp(&y.re).then(x.im.total_cmp(&y.im))
+        },
+        _ => match compare(a, b) {
+            Some(Ordering::Equal) => b.is_exact().cmp(&a.is_exact()),
+            Some(ord) => ord,
+            None => a.to_f64().total_cmp(&b.to_f64()),
+        },
+    }
+}
+
+fn check_divisor(func: &str, divisor: &Num) -> Result<(), LisperErr> {
+    if divisor.is_zero() {
+        return Err(LisperErr::new(LisperErrKind::DivisionByZero, format!("{} cannot divide by zero.", func)));
+    }
+    Ok(())
+}
+
+pub(crate) fn overflow_error(func: &str) -> LisperErr {
+    LisperErr::new(LisperErrKind::IntegerOverflow, format!("{} result is too large for an integer.", func))
+}
+
+// Returns a whole number argument, an integer or a whole float
+fn expect_whole(func: &str, arg: &LisperExp, pos: usize) -> Result<Num, LisperErr> {
+    match Num::from_exp(arg) {
+        Some(Num::Float(n)) if n.is_finite() && n.fract() == 0.0 => Ok(Num::Float(n)),
+        Some(num @ Num::Int(_)) | Some(num @ Num::Big(_)) => Ok(num),
+        _ => Err(type_error(func, pos, "whole number", arg)),
+    }
+}
+
+// Returns the exact value of a number argument as a fraction, and whether
+// the number was exact. Floats have to be finite.
+fn expect_fraction(func: &str, arg: &LisperExp, pos: usize) -> Result<(Rational, bool), LisperErr> {
+    let num = expect_real(func, arg, pos)?;
+    match num.to_ratio() {
+        Some(n) => Ok((n, true)),
+        None => match Rational::from_f64(num.to_f64()) {
+            Some(n) => Ok((n, false)),
+            None => Err(type_error(func, pos, "finite number", arg)),
+        },
+    }
+}
+
+// A fraction as an exact number, or as a float when it came from one
+fn fraction_result(n: Rational, exact: bool) -> LisperExp {
+    if exact {
+        Num::from_ratio(n).into()
+    } else {
+        LisperExp::Number(n.to_f64())
+    }
+}
+
+fn quot(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let a = expect_whole("quot", &args[0], 0)?;
+    let b = expect_whole("quot", &args[1], 1)?;
+    check_divisor("quot", &b)?;
+    Ok(combine(&a, &b, &QUOT).into())
+}
+
+fn rem(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let a = expect_whole("rem", &args[0], 0)?;
+    let b = expect_whole("rem", &args[1], 1)?;
+    Ok(modulus("rem", a, b)?.into())
+}
+
+fn numerator(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let (n, exact) = expect_fraction("numerator", &args[0], 0)?;
+    Ok(fraction_result(Rational::from(n.numer().clone()), exact))
+}
+
+fn denominator(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let (n, exact) = expect_fraction("denominator", &args[0], 0)?;
+    Ok(fraction_result(Rational::from(n.denom().clone()), exact))
+}
+
+// The result is exact if both arguments are
+fn rationalize(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let (x, x_exact) = expect_fraction("rationalize", &args[0], 0)?;
+    let (y, y_exact) = expect_fraction("rationalize", &args[1], 1)?;
+    let y = y.abs();
+    let simplest = Rational::simplest_between(&(&x - &y), &(&x + &y));
+    Ok(fraction_result(simplest, x_exact && y_exact))
+}
+
+// Complex numbers are inexact already
+fn exact_to_inexact(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match expect_num("exact->inexact", &args[0], 0)? {
+        Num::Complex(n) => Ok(LisperExp::Complex(n)),
+        n => Ok(LisperExp::Number(n.to_f64())),
+    }
+}
+
+fn inexact_to_exact(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let (n, _) = expect_fraction("inexact->exact", &args[0], 0)?;
+    Ok(Num::from_ratio(n).into())
+}
+
+// An exact zero imaginary part gives the real part as it is
+fn make_rectangular(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let re = expect_real("make-rectangular", &args[0], 0)?;
+    let im = expect_real("make-rectangular", &args[1], 1)?;
+    if im.is_exact() && im.is_zero() {
+        return Ok(re.into());
+    }
+    Ok(Num::from_complex(Complex::new(re.to_f64(), im.to_f64())).into())
+}
+
+fn make_polar(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let magnitude = expect_real("make-polar", &args[0], 0)?;
+    let angle = expect_real("make-polar", &args[1], 1)?;
+    Ok(Num::from_complex(Complex::from_polar(magnitude.to_f64(), angle.to_f64())).into())
+}
+
+// A real number is its own real part, with an exact zero imaginary part
+fn real_part(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match expect_num("real-part", &args[0], 0)? {
+        Num::Complex(n) => Ok(LisperExp::Number(n.re)),
+        real => Ok(real.into()),
+    }
+}
+
+fn imag_part(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match expect_num("imag-part", &args[0], 0)? {
+        Num::Complex(n) => Ok(LisperExp::Number(n.im)),
+        _ => Ok(LisperExp::Int(0)),
+    }
+}
+
+// The absolute value of a real number
+fn magnitude(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match expect_num("magnitude", &args[0], 0)? {
+        Num::Complex(n) => Ok(LisperExp::Number(n.norm())),
+        real if compare(&real, &Num::Int(0)) == Some(Ordering::Less) => Ok(sub("magnitude", Num::Int(0), real)?.into()),
+        real => Ok(real.into()),
+    }
+}
+
+// Zero for positive real numbers and π for negative ones
+fn angle(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match expect_num("angle", &args[0], 0)? {
+        Num::Complex(n) => Ok(LisperExp::Number(n.arg())),
+        Num::Float(n) => Ok(LisperExp::Number(Complex::new(n, 0.0).arg())),
+        real if compare(&real, &Num::Int(0)) == Some(Ordering::Less) => Ok(LisperExp::Number(std::f64::consts::PI)),
+        _ => Ok(LisperExp::Int(0)),
+    }
+}
+
+fn is_integer(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::Bool(matches!(args[0], LisperExp::Int(_) | LisperExp::BigInt(_))))
+}
+
+fn is_float(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::Bool(matches!(args[0], LisperExp::Number(_))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::eval_str;
+
+    #[test]
+    fn integer_arithmetic() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert_eq!(eval_str("(+ 1 2)", env)?, LisperExp::Int(3));
+        assert_eq!(eval_str("(% 10 3)", env)?, LisperExp::Int(1));
+        assert_eq!(eval_str("(/ 10 2)", env)?, LisperExp::Int(5));
+        assert_eq!(eval_str("(* 9007199254740993 1)", env)?, LisperExp::Int(9007199254740993));
+
+        // Mixing in a float gives a float
+        assert_eq!(eval_str("(+ 1 2.5)", env)?, LisperExp::Number(3.5));
+        assert_eq!(eval_str("(* 2 1.0)", env)?, LisperExp::Number(2.0));
+        assert_eq!(eval_str("(/ 7 2.0)", env)?, LisperExp::Number(3.5));
+        assert_eq!(eval_str("(+ 1 2.0)", env)?.to_string(), "3.0");
+
+        assert_eq!(eval_str("(quot 7 2)", env)?, LisperExp::Int(3));
+        assert_eq!(eval_str("(quot -7 2)", env)?, LisperExp::Int(-3));
+        assert_eq!(eval_str("(rem -7 2)", env)?, LisperExp::Int(-1));
+        assert_eq!(eval_str("(quot 7.0 2)", env)?, LisperExp::Number(3.0));
+        Ok(())
+    }
+
+    #[test]
+    fn big_integers() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        // Integers grow past 64 bits, and shrink back when they fit again
+        assert_eq!(eval_str("(+ 9223372036854775807 1)", env)?.to_string(), "9223372036854775808");
+        assert_eq!(eval_str("(* 4294967296 4294967296)", env)?.to_string(), "18446744073709551616");
+        assert_eq!(eval_str("(- -9223372036854775808 1)", env)?.to_string(), "-9223372036854775809");
+        assert_eq!(eval_str("(- 18446744073709551616 18446744073709551615)", env)?, LisperExp::Int(1));
+        assert_eq!(eval_str("(quot 100000000000000000000 3)", env)?.to_string(), "33333333333333333333");
+        assert_eq!(eval_str("(rem 100000000000000000000 3)", env)?, LisperExp::Int(1));
+        assert_eq!(eval_str("(integer? 100000000000000000000)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(pow 2 100)", env)?.to_string(), "1267650600228229401496703205376");
+        assert_eq!(eval_str("(abs -9223372036854775808)", env)?.to_string(), "9223372036854775808");
+        Ok(())
+    }
+
+    #[test]
+    fn rationals() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert_eq!(eval_str("(/ 1 3)", env)?.to_string(), "1/3");
+        assert_eq!(eval_str("(+ 1/3 1/6)", env)?.to_string(), "1/2");
+        assert_eq!(eval_str("(* 2/3 3/2)", env)?, LisperExp::Int(1));
+        assert_eq!(eval_str("'6/-4", env)?, LisperExp::Symbol("6/-4".to_string()));
+        assert_eq!(eval_str("-6/4", env)?.to_string(), "-3/2");
+        assert_eq!(eval_str("4/2", env)?, LisperExp::Int(2));
+        assert_eq!(eval_str("(+ 1/2 0.25)", env)?, LisperExp::Number(0.75));
+        assert_eq!(eval_str("(% 7/2 1)", env)?.to_string(), "1/2");
+        assert_eq!(eval_str("(< 1/3 0.3333333333333333)", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(= 1/2 0.5)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(numerator 6/4)", env)?, LisperExp::Int(3));
+        assert_eq!(eval_str("(denominator 6/4)", env)?, LisperExp::Int(2));
+        assert_eq!(eval_str("(denominator 5)", env)?, LisperExp::Int(1));
+        assert_eq!(eval_str("(denominator 0.5)", env)?, LisperExp::Number(2.0));
+        assert_eq!(eval_str("(rationalize 3/10 1/10)", env)?.to_string(), "1/3");
+        assert_eq!(eval_str("(rationalize 0.3 1/10)", env)?, LisperExp::Number(1.0 / 3.0));
+        assert_eq!(eval_str("(floor -7/2)", env)?, LisperExp::Int(-4));
+        assert_eq!(eval_str("(round 5/2)", env)?, LisperExp::Int(3));
+        assert_eq!(eval_str("(pow 2/3 -2)", env)?.to_string(), "9/4");
+        assert_eq!(eval_str("(max 1/2 1/3)", env)?.to_string(), "1/2");
+        assert_eq!(eval_str("(sort '(1/2 0.25 1/3))", env)?.to_string(), "(0.25 1/3 1/2)");
+        Ok(())
+    }
+
+    #[test]
+    fn conversions() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert_eq!(eval_str("(exact->inexact 3)", env)?, LisperExp::Number(3.0));
+        assert_eq!(eval_str("(inexact->exact 3.0)", env)?, LisperExp::Int(3));
+        assert_eq!(eval_str("(inexact->exact 3)", env)?, LisperExp::Int(3));
+        assert_eq!(eval_str("(inexact->exact 1.5)", env)?.to_string(), "3/2");
+        assert_eq!(eval_str("(inexact->exact 1e20)", env)?.to_string(), "100000000000000000000");
+        assert_eq!(eval_str("(exact->inexact 1/4)", env)?, LisperExp::Number(0.25));
+        assert_eq!(eval_str("(integer? 3)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(integer? 3.0)", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(float? 3.0)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(float? \"3\")", env)?, LisperExp::Bool(false));
+
+        // Only inf, +inf, -inf and nan are float words, others are symbols
+        assert_eq!(eval_str("(- inf)", env)?, LisperExp::Number(f64::NEG_INFINITY));
+        assert_eq!(eval_str("+inf", env)?, LisperExp::Number(f64::INFINITY));
+        assert_eq!(eval_str("nan", env)?.to_string(), "nan");
+        assert_eq!(eval_str("'infinity", env)?, LisperExp::Symbol("infinity".to_string()));
+        assert_eq!(eval_str("'NaN", env)?, LisperExp::Symbol("NaN".to_string()));
+        assert_eq!(eval_str("'INF", env)?, LisperExp::Symbol("INF".to_string()));
+        assert_eq!(eval_str("(string->number \"infinity\")", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(string->number \"-inf\")", env)?, LisperExp::Number(f64::NEG_INFINITY));
+        Ok(())
+    }
+
+    #[test]
+    fn comparisons() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert_eq!(eval_str("(= 1 1.0)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(equal? 1 1.0)", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(< 1 1.5 2)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(< 9007199254740993 9007199254740992.0)", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(> 9007199254740993 9007199254740992.0)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(< 1 nan)", env)?, LisperExp::Bool(false));
+        Ok(())
+    }
+
+    #[test]
+    fn complex_numbers() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        assert_eq!(eval_str("3+4i", env)?, LisperExp::Complex(Complex::new(3.0, 4.0)));
+        assert_eq!(eval_str("-2.5i", env)?, LisperExp::Complex(Complex::new(0.0, -2.5)));
+        assert_eq!(eval_str("1e3-i", env)?, LisperExp::Complex(Complex::new(1000.0, -1.0)));
+        assert_eq!(eval_str("1+0i", env)?, LisperExp::Number(1.0));
+        assert_eq!(eval_str("'nani", env)?, LisperExp::Symbol("nani".to_string()));
+        assert_eq!(eval_str("(+ 1+2i 3-2i)", env)?, LisperExp::Number(4.0));
+        assert_eq!(eval_str("(* +i +i)", env)?, LisperExp::Number(-1.0));
+        assert_eq!(eval_str("(* 2 1+i)", env)?.to_string(), "2+2i");
+        assert_eq!(eval_str("(/ 5 1+2i)", env)?.to_string(), "1-2i");
+        assert_eq!(eval_str("(pow 1+i 2)", env)?.to_string(), "0+2i");
+        assert_eq!(eval_str("(sqrt -3+4i)", env)?.to_string(), "1+2i");
+        assert_eq!(eval_str("(sqrt -4)", env)?.to_string(), "0+2i");
+        assert_eq!(eval_str("(sqrt -1/4)", env)?.to_string(), "0+0.5i");
+        assert_eq!(eval_str("(log -1)", env)?, LisperExp::Complex(Complex::new(0.0, std::f64::consts::PI)));
+        assert_eq!(eval_str("(log -8 2)", env)?.to_string(), (Complex::new(8f64.ln(), std::f64::consts::PI) / Complex::new(2f64.ln(), 0.0)).to_string());
+        assert_eq!(eval_str("(make-rectangular 1 2)", env)?.to_string(), "1+2i");
+        assert_eq!(eval_str("(make-rectangular 1/2 0)", env)?.to_string(), "1/2");
+        assert_eq!(eval_str("(real-part 3+4i)", env)?, LisperExp::Number(3.0));
+        assert_eq!(eval_str("(imag-part 3+4i)", env)?, LisperExp::Number(4.0));
+        assert_eq!(eval_str("(imag-part 3)", env)?, LisperExp::Int(0));
+        assert_eq!(eval_str("(magnitude 3+4i)", env)?, LisperExp::Number(5.0));
+        assert_eq!(eval_str("(magnitude -7/2)", env)?.to_string(), "7/2");
+        assert_eq!(eval_str("(angle -1)", env)?, LisperExp::Number(std::f64::consts::PI));
+        assert_eq!(eval_str("(angle +i)", env)?, LisperExp::Number(std::f64::consts::FRAC_PI_2));
+        assert_eq!(eval_str("(abs 3-4i)", env)?, LisperExp::Number(5.0));
+        assert_eq!(eval_str("(= 1+2i 1.0+2i)", env)?, LisperExp::Bool(true));
+        assert_eq!(eval_str("(= 1+2i 1)", env)?, LisperExp::Bool(false));
+        assert_eq!(eval_str("(string->number \"1-2i\")", env)?.to_string(), "1-2i");
+
+        // Infinite and NaN parts print so they read back
+        assert_eq!(eval_str("(* 1e300+1e300i 1e300)", env)?.to_string(), "inf+infi");
+        assert_eq!(eval_str("(make-rectangular 1 -inf)", env)?.to_string(), "1-infi");
+        assert_eq!(eval_str("nan+1i", env)?.to_string(), "nan+1i");
+        assert_eq!(eval_str("-inf-nani", env)?.to_string(), "-inf+nani");
+        assert_eq!(eval_str("'infi", env)?, LisperExp::Symbol("infi".to_string()));
+
+        // exp(iπ) + 1 is zero, up to rounding
+        match eval_str("(magnitude (+ (exp (* +i pi)) 1))", env)? {
+            LisperExp::Number(n) => assert!(n < 1e-15),
+            other => panic!("Unexpected result {}", other)
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn number_errors() {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        let kind = |expr: &str, env: &mut LisperEnv| eval_str(expr, env).map_err(|err| err.kind).err();
+        assert_eq!(kind("(/ 1/2 0)", env), Some(LisperErrKind::DivisionByZero));
+        assert_eq!(kind("1/0", env), Some(LisperErrKind::ParseError));
+        assert_eq!(kind("(quot 1 0)", env), Some(LisperErrKind::DivisionByZero));
+        assert_eq!(kind("(rem 1 0)", env), Some(LisperErrKind::DivisionByZero));
+        assert_eq!(kind("(quot 1.5 1)", env), Some(LisperErrKind::TypeError));
+        assert_eq!(kind("(quot 1/2 1)", env), Some(LisperErrKind::TypeError));
+        assert_eq!(kind("(inexact->exact nan)", env), Some(LisperErrKind::TypeError));
+        assert_eq!(kind("(rationalize inf 1)", env), Some(LisperErrKind::TypeError));
+        assert_eq!(kind("(pow 0 -1)", env), Some(LisperErrKind::DivisionByZero));
+        assert_eq!(kind("(< 1+i 2)", env), Some(LisperErrKind::TypeError));
+        assert_eq!(kind("(% 1+i 2)", env), Some(LisperErrKind::TypeError));
+        assert_eq!(kind("(floor 1+i)", env), Some(LisperErrKind::TypeError));
+        assert_eq!(kind("(/ 1+i 0)", env), Some(LisperErrKind::DivisionByZero));
+        match eval_str("(pow 10 10000000)", env) {
+            Err(err) => assert_eq!(err.message, "pow result is too large for an integer."),
+            _ => panic!("Unexpected result")
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use crate::bigint::BigInt;
+
+// An exact fraction. It is kept in lowest terms with a positive denominator,
+// so equal fractions have equal parts.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numer: BigInt,
+    denom: BigInt
+}
+
+impl Rational {
+    // Creates the fraction in lowest terms. Panics if the denominator is zero.
+    pub fn new(numer: BigInt, denom: BigInt) -> Rational {
+        assert!(!denom.is_zero(), "zero denominator");
+        let gcd = numer.gcd(&denom);
+        let (mut numer, _) = numer.div_rem(&gcd);
+        let (mut denom, _) = denom.div_rem(&gcd);
+        if denom.is_negative() {
+            numer = -&numer;
+            denom = -&denom;
+        }
+        Rational { numer, denom }
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.numer
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.denom
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denom == BigInt::one()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numer.is_zero()
+    }
+
+    pub fn abs(&self) -> Rational {
+        Rational { numer: self.numer.abs(), denom: self.denom.clone() }
+    }
+
+    // The exact value of a float, None for infinities and NaN
+    pub fn from_f64(n: f64) -> Option<Rational> {
+        if !n.is_finite() {
+            return None;
+        }
+        // A float is a 53 bit whole number times a power of two
+        let bits = n.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64;
+        let fraction = (bits & ((1 << 52) - 1)) as i64;
+        let (mantissa, exponent) = if exponent == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | (1 << 52), exponent - 1075)
+        };
+        let mantissa = BigInt::from(if n < 0.0 { -mantissa } else { mantissa });
+        if exponent >= 0 {
+            Some(Rational::from(mantissa.shl(exponent as usize)))
+        } else {
+            Some(Rational::new(mantissa, BigInt::one().shl(-exponent as usize)))
+        }
+    }
+
+    // The nearest float
+    pub fn to_f64(&self) -> f64 {
+        if self.is_zero() {
+            return 0.0;
+        }
+        // Divides to a quotient of about 65 bits, then adds a lowest bit
+        // that is set when there is a remainder, so it rounds correctly
+        let shift = self.denom.bits() as i64 - self.numer.bits() as i64 + 65;
+        let (quotient, remainder) = if shift >= 0 {
+            self.numer.shl(shift as usize).div_rem(&self.denom)
+        } else {
+            self.numer.div_rem(&self.denom.shl(-shift as usize))
+        };
+        let sticky = match (remainder.is_zero(), remainder.is_negative()) {
+            (true, _) => 0,
+            (false, false) => 1,
+            (false, true) => -1,
+        };
+        let quotient = &quotient.shl(1) + &BigInt::from(sticky);
+        scale(quotient.to_f64(), -(shift + 1))
+    }
+
+    // The largest integer less than or equal to the fraction
+    pub fn floor(&self) -> BigInt {
+        self.numer.div_floor(&self.denom)
+    }
+
+    pub fn ceil(&self) -> BigInt {
+        -&(-self).floor()
+    }
+
+    pub fn trunc(&self) -> BigInt {
+        self.numer.div_rem(&self.denom).0
+    }
+
+    // The nearest integer, halfway cases round away from zero
+    pub fn round(&self) -> BigInt {
+        let half = Rational::new(BigInt::one(), BigInt::from(2));
+        if self.numer.is_negative() {
+            (self - &half).ceil()
+        } else {
+            (self + &half).floor()
+        }
+    }
+
+    // Raises the fraction to an integer power. Panics if it is zero and the
+    // power is negative.
+    pub fn pow(&self, exponent: i64) -> Rational {
+        let power = exponent.unsigned_abs();
+        let (numer, denom) = (self.numer.pow(power), self.denom.pow(power));
+        if exponent < 0 {
+            Rational::new(denom, numer)
+        } else {
+            Rational { numer, denom }
+        }
+    }
+
+    // The simplest fraction between low and high, the one with the smallest
+    // denominator. It is found by walking down the Stern-Brocot tree: each
+    // step takes the whole part as the next continued fraction term and
+    // continues with the inverse of the rest, building up the convergents
+    // as it goes. Convergents are always in lowest terms.
+    pub fn simplest_between(low: &Rational, high: &Rational) -> Rational {
+        if low > high {
+            return Rational::simplest_between(high, low);
+        }
+        if low.numer.is_negative() && high.numer.is_negative() {
+            return -&Rational::simplest_between(&-high, &-low);
+        }
+        if low.numer.is_negative() || low.is_zero() {
+            return Rational::from(BigInt::zero());
+        }
+        // The bounds are kept as unreduced numerator and denominator pairs
+        let (mut low_numer, mut low_denom) = (low.numer.clone(), low.denom.clone());
+        let (mut high_numer, mut high_denom) = (high.numer.clone(), high.denom.clone());
+        let (mut numer, mut prev_numer) = (BigInt::one(), BigInt::zero());
+        let (mut denom, mut prev_denom) = (BigInt::zero(), BigInt::one());
+        loop {
+            let (whole, rest) = low_numer.div_rem(&low_denom);
+            let next = &whole + &BigInt::one();
+            // The last term is low itself when it is whole, or the next whole
+            // number when that is no greater than high
+            let last = if rest.is_zero() {
+                Some(&whole)
+            } else if &next * &high_denom <= high_numer {
+                Some(&next)
+            } else {
+                None
+            };
+            let term = last.unwrap_or(&whole);
+            let next_numer = &(term * &numer) + &prev_numer;
+            let next_denom = &(term * &denom) + &prev_denom;
+            if last.is_some() {
+                return Rational { numer: next_numer, denom: next_denom };
+            }
+            prev_numer = std::mem::replace(&mut numer, next_numer);
+            prev_denom = std::mem::replace(&mut denom, next_denom);
+            // Both are between whole and whole + 1, so continue with the
+            // inverses of what is left over, which swaps low and high
+            let high_rest = &high_numer - &(&whole * &high_denom);
+            low_numer = std::mem::replace(&mut high_denom, rest);
+            high_numer = std::mem::replace(&mut low_denom, high_rest);
+        }
+    }
+}
+
+// Multiplies by 2^exp, in steps so the power of two itself doesn't overflow
+fn scale(mut n: f64, mut exp: i64) -> f64 {
+    while exp > 1000 && n.is_finite() {
+        n *= 2f64.powi(1000);
+        exp -= 1000;
+    }
+    while exp < -1000 && n != 0.0 {
+        n *= 2f64.powi(-1000);
+        exp += 1000;
+    }
+    n * 2f64.powi(exp.clamp(-1100, 1100) as i32)
+}
+
+impl From<BigInt> for Rational {
+    fn from(n: BigInt) -> Rational {
+        Rational { numer: n, denom: BigInt::one() }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        (&self.numer * &other.denom).cmp(&(&other.numer * &self.denom))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Add<&'a Rational> for &'a Rational {
+    type Output = Rational;
+
+    fn add(self, other: &Rational) -> Rational {
+        Rational::new(
+            &(&self.numer * &other.denom) + &(&other.numer * &self.denom),
+            &self.denom * &other.denom
+        )
+    }
+}
+
+impl<'a> Sub<&'a Rational> for &'a Rational {
+    type Output = Rational;
+
+    fn sub(self, other: &Rational) -> Rational {
+        self + &-other
+    }
+}
+
+impl<'a> Mul<&'a Rational> for &'a Rational {
+    type Output = Rational;
+
+    fn mul(self, other: &Rational) -> Rational {
+        Rational::new(&self.numer * &other.numer, &self.denom * &other.denom)
+    }
+}
+
+// Panics if other is zero
+impl<'a> Div<&'a Rational> for &'a Rational {
+    type Output = Rational;
+
+    fn div(self, other: &Rational) -> Rational {
+        Rational::new(&self.numer * &other.denom, &self.denom * &other.numer)
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational { numer: -&self.numer, denom: self.denom.clone() }
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.numer, self.denom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(numer: i64, denom: i64) -> Rational {
+        Rational::new(BigInt::from(numer), BigInt::from(denom))
+    }
+
+    #[test]
+    fn lowest_terms() {
+        assert_eq!(ratio(6, -4).to_string(), "-3/2");
+        assert_eq!(ratio(0, 5), Rational::from(BigInt::zero()));
+        assert_eq!(&ratio(1, 3) + &ratio(1, 6), ratio(1, 2));
+        assert_eq!(&ratio(1, 3) - &ratio(1, 2), ratio(-1, 6));
+        assert_eq!(&ratio(2, 3) * &ratio(3, 4), ratio(1, 2));
+        assert_eq!(&ratio(2, 3) / &ratio(-4, 9), ratio(-3, 2));
+        assert_eq!(ratio(2, 3).pow(-2), ratio(9, 4));
+        assert!(ratio(1, 3) < ratio(1, 2));
+    }
+
+    #[test]
+    fn rounding() {
+        assert_eq!(ratio(7, 2).floor(), BigInt::from(3));
+        assert_eq!(ratio(-7, 2).floor(), BigInt::from(-4));
+        assert_eq!(ratio(-7, 2).ceil(), BigInt::from(-3));
+        assert_eq!(ratio(-7, 2).trunc(), BigInt::from(-3));
+        assert_eq!(ratio(5, 2).round(), BigInt::from(3));
+        assert_eq!(ratio(-5, 2).round(), BigInt::from(-3));
+        assert_eq!(ratio(7, 3).round(), BigInt::from(2));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(Rational::from_f64(0.75), Some(ratio(3, 4)));
+        assert_eq!(Rational::from_f64(-2.0), Some(ratio(-2, 1)));
+        assert_eq!(Rational::from_f64(0.1).map(|r| r.to_string()), Some("3602879701896397/36028797018963968".to_string()));
+        assert_eq!(Rational::from_f64(f64::NAN), None);
+        assert_eq!(ratio(1, 3).to_f64(), 1.0 / 3.0);
+        assert_eq!(Rational::from_f64(1e300).unwrap().to_f64(), 1e300);
+        assert_eq!(Rational::from_f64(5e-324).unwrap().to_f64(), 5e-324);
+    }
+
+    #[test]
+    fn simplest() {
+        assert_eq!(Rational::simplest_between(&ratio(3, 10), &ratio(4, 10)), ratio(1, 3));
+        assert_eq!(Rational::simplest_between(&ratio(-4, 10), &ratio(-3, 10)), ratio(-1, 3));
+        assert_eq!(Rational::simplest_between(&ratio(-1, 2), &ratio(1, 2)), ratio(0, 1));
+        assert_eq!(Rational::simplest_between(&ratio(3, 2), &ratio(5, 2)), ratio(2, 1));
+        assert_eq!(Rational::simplest_between(&ratio(7, 4), &ratio(7, 4)), ratio(7, 4));
+        assert_eq!(Rational::simplest_between(&ratio(355, 113), &ratio(22, 7)), ratio(22, 7));
+        assert_eq!(Rational::simplest_between(&ratio(31, 10), &ratio(32, 10)), ratio(16, 5));
+        // Ratios of consecutive Fibonacci numbers have thousands of terms
+        let (mut a, mut b) = (BigInt::one(), BigInt::one());
+        for _ in 0..3000 {
+            let c = &a + &b;
+            a = std::mem::replace(&mut b, c);
+        }
+        let golden = Rational::new(b.clone(), a.clone());
+        assert_eq!(Rational::simplest_between(&golden, &golden), golden);
+    }
+}
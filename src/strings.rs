@@ -0,0 +1,328 @@
+use super::*;
+
+// Adds the string functions to an env
+pub(crate) fn register(env: &LisperEnv) {
+    env.register("str", LisperArity::AtLeast(0), "Concatenates the values into a string.", str_concat);
+    env.register("string?", LisperArity::Exact(1), "True if the value is a string.", is_string);
+    env.register("string-length", LisperArity::Exact(1), "The number of chars in a string.", string_length);
+    env.register("substring", LisperArity::Range(2, 3), "The chars of a string from start up to, but not including, end.", substring);
+    env.register("split", LisperArity::Exact(2), "Splits a string on a separator into a list of strings.", split);
+    env.register("join", LisperArity::Range(1, 2), "Joins a list of values into a string, with an optional separator.", join);
+    env.register("trim", LisperArity::Exact(1), "Removes whitespace from both ends of a string.", trim);
+    env.register("upcase", LisperArity::Exact(1), "Converts a string to upper case.", upcase);
+    env.register("downcase", LisperArity::Exact(1), "Converts a string to lower case.", downcase);
+    env.register("starts-with?", LisperArity::Exact(2), "True if the string starts with the prefix.", starts_with);
+    env.register("ends-with?", LisperArity::Exact(2), "True if the string ends with the suffix.", ends_with);
+    env.register("contains?", LisperArity::Exact(2), "True if the string contains the other string.", contains);
+    env.register("replace", LisperArity::Exact(3), "Replaces every occurrence of a string with another.", replace);
+    env.register("string->number", LisperArity::Exact(1), "Reads a number from a string, false if it isn't one.", string_to_number);
+    env.register("number->string", LisperArity::Exact(1), "Writes a number as a string.", number_to_string);
+    env.register("format", LisperArity::AtLeast(1), "Fills the {} placeholders in a string with the values.", format);
+}
+
+fn str_concat(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let res: String = args.iter().map(LisperExp::display).collect();
+    Ok(LisperExp::Str(res))
+}
+
+fn is_string(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::Bool(matches!(args[0], LisperExp::Str(_))))
+}
+
+fn string_length(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("string-length", &args[0], 0)?;
+    Ok(LisperExp::Int(s.chars().count() as i64))
+}
+
+fn substring(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let chars: Vec<char> = expect_string("substring", &args[0], 0)?.chars().collect();
+    let start = expect_index("substring", &args[1], 1)?;
+    let end = match args.get(2) {
+        Some(arg) => expect_index("substring", arg, 2)?,
+        None => chars.len(),
+    };
+
+    if end > chars.len() {
+        return Err(index_error("substring", end, chars.len()));
+    }
+    if start > end {
+        return Err(LisperErr::new(
+            LisperErrKind::IndexOutOfRange,
+            format!("substring start {} is after end {}.", start, end)
+        ));
+    }
+
+    Ok(LisperExp::Str(chars[start..end].iter().collect()))
+}
+
+fn split(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("split", &args[0], 0)?;
+    let separator = expect_string("split", &args[1], 1)?;
+
+    // An empty separator splits the string into its chars
+    let parts: Vec<LisperExp> = if separator.is_empty() {
+        s.chars().map(|c| LisperExp::Str(c.to_string())).collect()
+    } else {
+        s.split(separator).map(|part| LisperExp::Str(part.to_string())).collect()
+    };
+
+    Ok(LisperExp::List(parts.into()))
+}
+
+fn join(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = expect_list("join", &args[0], 0)?;
+    let separator = match args.get(1) {
+        Some(arg) => expect_string("join", arg, 1)?,
+        None => "",
+    };
+
+    let parts: Vec<String> = items.iter().map(LisperExp::display).collect();
+    Ok(LisperExp::Str(parts.join(separator)))
+}
+
+fn trim(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("trim", &args[0], 0)?;
+    Ok(LisperExp::Str(s.trim().to_string()))
+}
+
+fn upcase(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("upcase", &args[0], 0)?;
+    Ok(LisperExp::Str(s.to_uppercase()))
+}
+
+fn downcase(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("downcase", &args[0], 0)?;
+    Ok(LisperExp::Str(s.to_lowercase()))
+}
+
+fn starts_with(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("starts-with?", &args[0], 0)?;
+    let prefix = expect_string("starts-with?", &args[1], 1)?;
+    Ok(LisperExp::Bool(s.starts_with(prefix)))
+}
+
+fn ends_with(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("ends-with?", &args[0], 0)?;
+    let suffix = expect_string("ends-with?", &args[1], 1)?;
+    Ok(LisperExp::Bool(s.ends_with(suffix)))
+}
+
+fn contains(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("contains?", &args[0], 0)?;
+    let other = expect_string("contains?", &args[1], 1)?;
+    Ok(LisperExp::Bool(s.contains(other)))
+}
+
+fn replace(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("replace", &args[0], 0)?;
+    let from = expect_string("replace", &args[1], 1)?;
+    let to = expect_string("replace", &args[2], 2)?;
+
+    if from.is_empty() {
+        return Err(LisperErr::new(LisperErrKind::TypeError, "replace expects a non-empty string to replace."));
+    }
+    Ok(LisperExp::Str(s.replace(from, to)))
+}
+
+fn string_to_number(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let s = expect_string("string->number", &args[0], 0)?;
+    match numbers::parse_number(s.trim()) {
+        Some(Ok(n)) => Ok(n),
+        _ => Ok(LisperExp::Bool(false)),
+    }
+}
+
+fn number_to_string(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let n = numbers::expect_num("number->string", &args[0], 0)?;
+    Ok(LisperExp::Str(LisperExp::from(n).to_string()))
+}
+
+// Replaces each {} in the format string with the next value. Literal braces
+// are written as {{ and }}.
+fn format(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let template = expect_string("format", &args[0], 0)?;
+    let mut values = args[1..].iter();
+    let mut res = String::new();
+
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                res.push(c);
+                chars.next();
+            },
+            ('{', Some('}')) => {
+                chars.next();
+                let value = values.next().ok_or_else(|| LisperErr::new(
+                    LisperErrKind::ArityError,
+                    format!("format has more placeholders than the {} values given.", args.len() - 1)
+                ))?;
+                res.push_str(&value.display());
+            },
+            ('{', _) | ('}', _) => return Err(LisperErr::new(
+                LisperErrKind::SyntaxError,
+                format!("format found an unmatched {}, use {{}} for a value or {}{} for the char.", c, c, c)
+            )),
+            _ => res.push(c),
+        }
+    }
+
+    if values.next().is_some() {
+        return Err(LisperErr::new(
+            LisperErrKind::ArityError,
+            format!("format has fewer placeholders than the {} values given.", args.len() - 1)
+        ));
+    }
+
+    Ok(LisperExp::Str(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::eval_str;
+
+    // Evaluates an expression that should return a string
+    fn eval_string(expr: &str) -> Result<String, Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+        match eval_str(expr, env)? {
+            LisperExp::Str(s) => Ok(s),
+            other => Err(format!("expected a string, got {}", other).into()),
+        }
+    }
+
+    #[test]
+    fn str_concat() -> Result<(),  Box<dyn std::error::Error>> {
+        assert_eq!(eval_string("(str \"a\" 1 \"b\" true)")?, "a1btrue");
+        assert_eq!(eval_string("(str)")?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn string_length_unicode() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+        match eval_str("(string-length \"héllo wörld π\")", env)? {
+            LisperExp::Int(n) => assert_eq!(n, 13),
+            _ => panic!("Unexpected result")
+        }
+        assert!(eval_str("(string-length 5)", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn substring_chars() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        assert_eq!(eval_string("(substring \"πάντα ῥεῖ\" 6 9)")?, "ῥεῖ");
+        assert_eq!(eval_string("(substring \"πάντα ῥεῖ\" 6)")?, "ῥεῖ");
+        assert_eq!(eval_string("(substring \"abc\" 3)")?, "");
+
+        let env = &mut create_default_env();
+        match eval_str("(substring \"abc\" 1 4)", env) {
+            Err(err) => {
+                assert_eq!(err.kind, LisperErrKind::IndexOutOfRange);
+                assert_eq!(err.message, "substring index 4 is out of range for length 3.");
+            },
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(substring \"abc\" 2 1)", env) {
+            Err(err) => {
+                assert_eq!(err.kind, LisperErrKind::IndexOutOfRange);
+                assert_eq!(err.message, "substring start 2 is after end 1.");
+            },
+            _ => panic!("Unexpected result")
+        }
+        assert_eq!(eval_str("(substring \"abc\" -1)", env).map_err(|err| err.kind).err(), Some(LisperErrKind::TypeError));
+        assert_eq!(eval_str("(substring \"abc\" 1.5)", env).map_err(|err| err.kind).err(), Some(LisperErrKind::TypeError));
+        Ok(())
+    }
+
+    #[test]
+    fn split_join() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+        match eval_str("(split \"a,b,,c\" \",\")", env)? {
+            LisperExp::List(parts) => assert_eq!(parts.len(), 4),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(split \"añb\" \"\")", env)? {
+            LisperExp::List(parts) => assert_eq!(parts.len(), 3),
+            _ => panic!("Unexpected result")
+        }
+        assert_eq!(eval_string("(join (split \"a b c\" \" \") \"-\")")?, "a-b-c");
+        assert_eq!(eval_string("(join (split \"a b c\" \" \"))")?, "abc");
+        assert!(eval_str("(join \"abc\")", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn case_and_trim() -> Result<(),  Box<dyn std::error::Error>> {
+        assert_eq!(eval_string("(trim \"  hi there \\n\")")?, "hi there");
+        assert_eq!(eval_string("(upcase \"straße\")")?, "STRASSE");
+        assert_eq!(eval_string("(downcase \"ÀÉÎ\")")?, "àéî");
+        Ok(())
+    }
+
+    #[test]
+    fn predicates() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+        let check = |expr: &str, env: &mut LisperEnv| match eval_str(expr, env) {
+            Ok(LisperExp::Bool(b)) => b,
+            _ => panic!("Unexpected result")
+        };
+        assert!(check("(starts-with? \"hello\" \"he\")", env));
+        assert!(!check("(starts-with? \"hello\" \"lo\")", env));
+        assert!(check("(ends-with? \"hello\" \"lo\")", env));
+        assert!(check("(contains? \"hello\" \"ell\")", env));
+        assert!(!check("(contains? \"hello\" \"xyz\")", env));
+        assert!(check("(string? \"hello\")", env));
+        assert!(!check("(string? 1)", env));
+        assert!(eval_str("(contains? \"hello\" 1)", env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn replace_all() -> Result<(),  Box<dyn std::error::Error>> {
+        assert_eq!(eval_string("(replace \"a-b-c\" \"-\" \"+\")")?, "a+b+c");
+        assert!(eval_string("(replace \"abc\" \"\" \"x\")").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn number_conversion() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+        match eval_str("(+ 1 (string->number \" 2.5 \"))", env)? {
+            LisperExp::Number(n) => assert_eq!(n, 3.5),
+            _ => panic!("Unexpected result")
+        }
+        match eval_str("(string->number \"abc\")", env)? {
+            LisperExp::Bool(b) => assert!(!b),
+            _ => panic!("Unexpected result")
+        }
+        assert_eq!(eval_string("(number->string 42)")?, "42");
+        assert_eq!(eval_string("(number->string 0.5)")?, "0.5");
+        Ok(())
+    }
+
+    #[test]
+    fn format_placeholders() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        assert_eq!(eval_string("(format \"{} + {} = {}\" 1 2 (+ 1 2))")?, "1 + 2 = 3");
+        assert_eq!(eval_string("(format \"{{{}}} is {}\" \"x\" \"ü\")")?, "{x} is ü");
+
+        let env = &mut create_default_env();
+        assert_eq!(eval_str("(format \"{} {}\" 1)", env).map_err(|err| err.kind).err(), Some(LisperErrKind::ArityError));
+        assert_eq!(eval_str("(format \"{}\" 1 2)", env).map_err(|err| err.kind).err(), Some(LisperErrKind::ArityError));
+        assert!(eval_str("(format \"{x}\")", env).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+use super::*;
+
+// What a pattern variable matched. Variables under an ellipsis match a
+// sequence, with one level of Many per ellipsis.
+#[derive(Clone, Debug)]
+enum Binding {
+    One(LisperExp),
+    Many(Vec<Binding>)
+}
+
+type Bindings = HashMap<String, Binding>;
+
+// Evaluates (define-syntax name (syntax-rules (literals...) (pattern template)...))
+pub(crate) fn eval_define_syntax(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let (name, spec) = match args {
+        [LisperExp::Symbol(name), LisperExp::List(spec)] => (name, spec),
+        _ => return Err(LisperErr::new(LisperErrKind::SyntaxError, "define-syntax expects a name and a syntax-rules form.")),
+    };
+
+    let (literals, rules) = match &spec[..] {
+        [LisperExp::Symbol(head), LisperExp::List(literals), rules @ ..] if head == "syntax-rules" => (literals, rules),
+        _ => return Err(LisperErr::new(LisperErrKind::SyntaxError, "syntax-rules expects a list of literals and rules.")),
+    };
+
+    let mut literal_names: Vec<String> = vec![];
+    for literal in literals.iter() {
+        match literal {
+            LisperExp::Symbol(literal) => literal_names.push(literal.clone()),
+            other => return Err(
+                LisperErr::new(LisperErrKind::SyntaxError, format!("syntax-rules literals must be symbols, got {}.", other))
+            ),
+        }
+    }
+
+    let mut rule_pairs: Vec<(LisperExp, LisperExp)> = vec![];
+    for rule in rules.iter() {
+        match rule {
+            LisperExp::List(rule) if rule.len() == 2 && matches!(rule[0], LisperExp::List(_)) => {
+                rule_pairs.push((rule[0].clone(), rule[1].clone()))
+            },
+            other => return Err(
+                LisperErr::new(LisperErrKind::SyntaxError, format!("syntax-rules expects ((pattern...) template) rules, got {}.", other))
+            ),
+        }
+    }
+
+    let mark = env.next_mark();
+    env.state.macro_envs.borrow_mut().insert(mark, env.clone());
+    let rules = LisperSyntaxRules {
+        name: name.clone(),
+        literals: Rc::new(literal_names),
+        rules: Rc::new(rule_pairs),
+        env: env.clone(),
+        mark
+    };
+    env.define(name, LisperExp::SyntaxRules(Rc::new(rules)));
+
+    Ok(LisperExp::Symbol(name.clone()))
+}
+
+// Expands a call to a syntax-rules macro with the first rule whose pattern
+// matches the unevaluated arguments
+pub(crate) fn expand(rules: &LisperSyntaxRules, args: &[LisperExp]) -> Result<LisperExp, LisperErr> {
+    for (pattern, template) in rules.rules.iter() {
+        // The first item of the pattern stands for the macro name, and is ignored
+        let pattern = match pattern {
+            LisperExp::List(items) if !items.is_empty() => &items[1..],
+            _ => &[],
+        };
+
+        let mut bindings = Bindings::new();
+        if match_list(pattern, args, &rules.literals, &mut bindings) {
+            let renamer = Renamer { macro_mark: rules.mark, mark: rules.env.next_mark() };
+            return expand_template(template, &bindings, &renamer, Quoting::Code);
+        }
+    }
+
+    let call: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    Err(LisperErr::new(
+        LisperErrKind::SyntaxError,
+        format!("no rule of {} matches ({} {}).", rules.name, rules.name, call.join(" "))
+    ))
+}
+
+fn is_ellipsis(exp: &LisperExp) -> bool {
+    matches!(exp, LisperExp::Symbol(sym) if sym == "...")
+}
+
+// Matches a form against a pattern, adding the pattern variables to bindings
+fn match_pattern(pattern: &LisperExp, form: &LisperExp, literals: &[String], bindings: &mut Bindings) -> bool {
+    match (pattern, form) {
+        (LisperExp::Symbol(sym), _) if sym == "_" => true,
+        (LisperExp::Symbol(sym), _) if literals.contains(sym) => {
+            matches!(form, LisperExp::Symbol(other) if other == sym)
+        },
+        (LisperExp::Symbol(sym), _) => {
+            bindings.insert(sym.clone(), Binding::One(form.clone()));
+            true
+        },
+        (LisperExp::List(patterns), LisperExp::List(items)) => match_list(patterns, items, literals, bindings),
+        (LisperExp::Int(a), LisperExp::Int(b)) => a == b,
+        (LisperExp::BigInt(a), LisperExp::BigInt(b)) => a == b,
+        (LisperExp::Rational(a), LisperExp::Rational(b)) => a == b,
+        (LisperExp::Complex(a), LisperExp::Complex(b)) => a == b,
+        (LisperExp::Number(a), LisperExp::Number(b)) => a == b,
+        (LisperExp::Str(a), LisperExp::Str(b)) => a == b,
+        (LisperExp::Bool(a), LisperExp::Bool(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Matches the items of a list against a list of patterns, where one pattern
+// can be followed by ... to match any number of items
+fn match_list(patterns: &[LisperExp], items: &[LisperExp], literals: &[String], bindings: &mut Bindings) -> bool {
+    let ellipsis = patterns.iter().position(is_ellipsis);
+    let (before, repeated, after) = match ellipsis {
+        Some(i) if i > 0 => (&patterns[..i - 1], &patterns[i - 1], &patterns[i + 1..]),
+        _ => {
+            return patterns.len() == items.len()
+                && patterns.iter().zip(items).all(|(pattern, item)| match_pattern(pattern, item, literals, bindings));
+        },
+    };
+
+    if items.len() < before.len() + after.len() {
+        return false;
+    }
+    let tail_start = items.len() - after.len();
+
+    let fixed_match = before.iter().zip(items).all(|(pattern, item)| match_pattern(pattern, item, literals, bindings))
+        && after.iter().zip(&items[tail_start..]).all(|(pattern, item)| match_pattern(pattern, item, literals, bindings));
+    if !fixed_match {
+        return false;
+    }
+
+    // Every variable of the repeated pattern gets one binding per item
+    let mut sequences: HashMap<String, Vec<Binding>> = pattern_vars(repeated, literals)
+        .into_iter()
+        .map(|var| (var, vec![]))
+        .collect();
+    for item in items[before.len()..tail_start].iter() {
+        let mut item_bindings = Bindings::new();
+        if !match_pattern(repeated, item, literals, &mut item_bindings) {
+            return false;
+        }
+        for (var, binding) in item_bindings {
+            if let Some(sequence) = sequences.get_mut(&var) {
+                sequence.push(binding);
+            }
+        }
+    }
+    for (var, sequence) in sequences {
+        bindings.insert(var, Binding::Many(sequence));
+    }
+    true
+}
+
+// The names of the pattern variables in a pattern
+fn pattern_vars(pattern: &LisperExp, literals: &[String]) -> Vec<String> {
+    match pattern {
+        LisperExp::Symbol(sym) if sym != "_" && sym != "..." && !literals.contains(sym) => vec![sym.clone()],
+        LisperExp::List(items) => items.iter().flat_map(|item| pattern_vars(item, literals)).collect(),
+        _ => vec![],
+    }
+}
+
+// Renames the symbols a template introduces, with a mark that is new for
+// each expansion, so bindings the template introduces can't capture the
+// caller's variables, or those of other expansions. Where the expansion
+// doesn't bind a renamed symbol, it refers to the binding where the macro was
+// defined, found through the mark of the macro, so the caller's bindings
+// can't shadow it.
+struct Renamer {
+    macro_mark: usize,
+    mark: usize
+}
+
+impl Renamer {
+    fn rename(&self, sym: &str) -> String {
+        if SPECIAL_FORMS.contains(&sym) {
+            return sym.to_string();
+        }
+        format!("{}#{}.{}", sym, self.macro_mark, self.mark)
+    }
+}
+
+// Whether the part of a template being expanded is code or data. Symbols in
+// data are not renamed, except in the unquoted parts of a quasiquote.
+#[derive(Clone, Copy)]
+enum Quoting {
+    Code,
+    Quoted,
+    // Inside this many levels of quasiquote
+    Quasiquoted(usize)
+}
+
+impl Quoting {
+    // The quoting of the items of a list whose first item is head
+    fn enter(self, head: &str) -> Quoting {
+        match (self, head) {
+            (Quoting::Code, "quote") => Quoting::Quoted,
+            (Quoting::Code, "quasiquote") => Quoting::Quasiquoted(1),
+            (Quoting::Quasiquoted(depth), "quasiquote") => Quoting::Quasiquoted(depth + 1),
+            (Quoting::Quasiquoted(1), "unquote" | "unquote-splicing") => Quoting::Code,
+            (Quoting::Quasiquoted(depth), "unquote" | "unquote-splicing") => Quoting::Quasiquoted(depth - 1),
+            (quoting, _) => quoting,
+        }
+    }
+}
+
+// Expands an item followed by depth ellipses into res, once for each match
+// of the sequence variables it contains
+fn expand_repeated(item: &LisperExp, depth: usize, bindings: &Bindings, renamer: &Renamer, quoting: Quoting, res: &mut Vec<LisperExp>) -> Result<(), LisperErr> {
+    if depth == 0 {
+        res.push(expand_template(item, bindings, renamer, quoting)?);
+        return Ok(());
+    }
+
+    let vars: Vec<String> = pattern_vars(item, &[])
+        .into_iter()
+        .filter(|var| matches!(bindings.get(var), Some(Binding::Many(_))))
+        .collect();
+    let mut count: Option<usize> = None;
+    for var in vars.iter() {
+        if let Some(Binding::Many(sequence)) = bindings.get(var) {
+            if count.is_some_and(|count| count != sequence.len()) {
+                return Err(LisperErr::new(
+                    LisperErrKind::SyntaxError,
+                    format!("pattern variables in {} ... match different numbers of items.", item)
+                ));
+            }
+            count = Some(sequence.len());
+        }
+    }
+    let count = count.ok_or_else(|| LisperErr::new(
+        LisperErrKind::SyntaxError,
+        format!("{} ... in a template must contain a pattern variable followed by ... in the pattern.", item)
+    ))?;
+
+    for n in 0..count {
+        let mut item_bindings = bindings.clone();
+        for var in vars.iter() {
+            if let Some(Binding::Many(sequence)) = bindings.get(var) {
+                item_bindings.insert(var.clone(), sequence[n].clone());
+            }
+        }
+        expand_repeated(item, depth - 1, &item_bindings, renamer, quoting, res)?;
+    }
+    Ok(())
+}
+
+// Fills in a template with the pattern variables. Symbols inside a quote are
+// data, so they are not renamed, and neither are those inside a quasiquote
+// unless they are unquoted.
+fn expand_template(template: &LisperExp, bindings: &Bindings, renamer: &Renamer, quoting: Quoting) -> Result<LisperExp, LisperErr> {
+    let items = match template {
+        LisperExp::Symbol(sym) => return match bindings.get(sym) {
+            Some(Binding::One(value)) => Ok(value.clone()),
+            Some(Binding::Many(_)) => Err(LisperErr::new(
+                LisperErrKind::SyntaxError,
+                format!("pattern variable {} must be followed by ... in the template.", sym)
+            )),
+            None => match quoting {
+                Quoting::Code => Ok(LisperExp::Symbol(renamer.rename(sym))),
+                Quoting::Quoted | Quoting::Quasiquoted(_) => Ok(LisperExp::Symbol(sym.clone())),
+            },
+        },
+        LisperExp::List(items) => items,
+        other => return Ok(other.clone()),
+    };
+
+    let quoting = match items.first() {
+        Some(LisperExp::Symbol(head)) if !bindings.contains_key(head) => quoting.enter(head),
+        _ => quoting,
+    };
+
+    let mut res: Vec<LisperExp> = vec![];
+    let mut i = 0;
+    while i < items.len() {
+        // Each ... after an item repeats it over one more level of matches
+        let depth = items[i + 1..].iter().take_while(|item| is_ellipsis(item)).count();
+        expand_repeated(&items[i], depth, bindings, renamer, quoting, &mut res)?;
+        i += 1 + depth;
+    }
+
+    Ok(LisperExp::List(res.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::eval_str;
+
+    // Evaluates the expressions in order in a new default env, and prints the
+    // result of the last one
+    fn eval_all(exprs: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env = &mut create_default_env();
+        let mut res = LisperExp::List(vec![].into());
+        for expr in exprs.iter() {
+            res = eval_str(expr, env)?;
+        }
+        Ok(res.to_string())
+    }
+
+    const SWAP: &str = "(define-syntax swap! (syntax-rules () ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))";
+    const MY_OR: &str = "(define-syntax my-or (syntax-rules ()
+        ((_) false)
+        ((_ e) e)
+        ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))";
+
+    #[test]
+    fn expand_rules() -> Result<(),  Box<dyn std::error::Error>> {
+        assert_eq!(eval_all(&[SWAP, "(def x 1)", "(def y 2)", "(swap! x y)", "(list x y)"])?, "(2 1)");
+        assert_eq!(eval_all(&[MY_OR, "(my-or)"])?, "false");
+        assert_eq!(eval_all(&[MY_OR, "(my-or false '() 3 (error \"not reached\"))"])?, "3");
+        assert_eq!(eval_all(&[MY_OR, "my-or"])?, "#<macro my-or>");
+        Ok(())
+    }
+
+    #[test]
+    fn hygiene() -> Result<(),  Box<dyn std::error::Error>> {
+        // The tmp the macro binds is not the caller's tmp
+        assert_eq!(eval_all(&[SWAP, "(def tmp 1)", "(def other 2)", "(swap! tmp other)", "(list tmp other)"])?, "(2 1)");
+        assert_eq!(eval_all(&[MY_OR, "(def t 5)", "(my-or false t)"])?, "5");
+
+        // The macro's if and list refer to the bindings where it was defined,
+        // even when the caller shadows them
+        assert_eq!(eval_all(&[
+            "(define-syntax pair (syntax-rules () ((_ a b) (list a b))))",
+            "(let ((list 0)) (pair list 1))",
+        ])?, "(0 1)");
+
+        // Quoted symbols are data and keep their names, in a quasiquote only
+        // the unquoted parts are code
+        assert_eq!(eval_all(&["(define-syntax tag (syntax-rules () ((_ x) (list 'tag x))))", "(tag 1)"])?, "(tag 1)");
+        assert_eq!(eval_all(&["(define-syntax tag (syntax-rules () ((_ x) `(,x foo))))", "(tag 1)"])?, "(1 foo)");
+        assert_eq!(eval_all(&[
+            "(define-syntax tag (syntax-rules () ((_ x) (let ((y x)) `(foo ,y ,@(list y) `(bar ,,y))))))",
+            "(tag 1)",
+        ])?, "(foo 1 1 `(bar ,1))");
+
+        // Each expansion gets its own names, so a binding made by one
+        // expansion doesn't capture the symbols of another, even when one
+        // expands inside the other
+        let m = "(define-syntax m (syntax-rules () ((_) t) ((_ body) (let ((t 1)) body))))";
+        assert_eq!(eval_all(&["(def t 5)", m, "(m (m))"])?, "5");
+
+        // Symbols refer to the bindings where the macro was defined when it
+        // is used, not when it is expanded
+        assert_eq!(eval_all(&[m, "(def t 6)", "(m (m))"])?, "6");
+        assert_eq!(eval_all(&[
+            "(define-syntax later (syntax-rules () ((_) (helper))))",
+            "(defn use-later () (later))",
+            "(defn helper () 7)",
+            "(use-later)",
+        ])?, "7");
+        Ok(())
+    }
+
+    #[test]
+    fn ellipsis_and_literals() -> Result<(),  Box<dyn std::error::Error>> {
+        let for_each = "(define-syntax for (syntax-rules (in)
+            ((_ x in xs body ...) (map (fn (x) body ...) xs))))";
+        assert_eq!(eval_all(&[for_each, "(for n in '(1 2 3) (* n n))"])?, "(1 4 9)");
+        assert!(eval_all(&[for_each, "(for n on '(1 2 3) (* n n))"]).is_err());
+
+        // Nested ellipses, and patterns after an ellipsis
+        let my_let = "(define-syntax my-let (syntax-rules ()
+            ((_ ((name value) ...) body ... last) ((fn (name ...) body ... last) value ...))))";
+        assert_eq!(eval_all(&[my_let, "(my-let ((a 1) (b 2)) (+ a b))"])?, "3");
+        assert_eq!(eval_all(&[my_let, "(my-let () 1 2)"])?, "2");
+
+        let flatten = "(define-syntax flat (syntax-rules () ((_ (x ...) ...) '(x ... ...))))";
+        assert_eq!(eval_all(&[flatten, "(flat (1 2) (3))"])?, "(1 2 3)");
+        Ok(())
+    }
+
+    #[test]
+    fn macroexpand_rules() -> Result<(),  Box<dyn std::error::Error>> {
+        let expanded = eval_all(&[SWAP, "(macroexpand '(swap! x y))"])?;
+        assert!(expanded.starts_with("(let ((tmp#"));
+        assert!(expanded.contains("(set! x y)"));
+        Ok(())
+    }
+
+    #[test]
+    fn rule_errors() {
+        use super::*;
+
+        let env = &mut create_default_env();
+
+        eval_str(SWAP, env).unwrap();
+        match eval_str("(swap! x)", env) {
+            Err(err) => assert_eq!(err.message, "no rule of swap! matches (swap! x)."),
+            _ => panic!("Unexpected result")
+        }
+        assert!(eval_str("(define-syntax bad (syntax-rules ((_ a ...) a)))", env).is_err());
+        assert!(eval_str("(define-syntax bad (syntax-rules () ((_ a ...) a)))", env).is_ok());
+        assert!(eval_str("(bad 1 2)", env).is_err());
+        assert!(eval_str("(syntax-rules () ((_ a) a))", env).is_err());
+    }
+}
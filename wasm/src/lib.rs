@@ -1,5 +1,8 @@
 use wasm_bindgen::prelude::*;
-use lisper::*;
+
+// Wasm gets a 1MB stack by default, less than the default limit allows evals
+// to use
+const MAX_STACK_SIZE: usize = 512 * 1024;
 
 #[wasm_bindgen]
 extern {
@@ -15,19 +18,17 @@ pub fn greet() {
 pub fn run(exp: String) -> String {
     // Create lisper environment
     let env = &mut lisper::create_default_env();
+    env.set_max_stack_size(MAX_STACK_SIZE);
     // Evaluate the string as a lisper expression
     match evaluate(exp, env) {
         Ok(res) => res.to_string(),
-        Err(e) => match e {
-            LisperErr::Reason(msg) => msg.to_string(),
-        },
+        Err(e) => e.to_string(),
     }
 }
 
 fn evaluate(exp:String, env: &mut lisper::LisperEnv) -> Result<String, lisper::LisperErr> {
-    let tokens:Vec<String> = lisper::tokenize(exp);
-    let (parsed_tokens, _) = lisper::parse(&tokens)?;
-    let eval_out = lisper::eval(parsed_tokens, env)?;
+    let forms:Vec<lisper::LisperExp> = lisper::read_all(&exp)?;
+    let eval_out = lisper::eval_program(&forms, env)?;
 
     Ok(eval_out.to_string())
 }
@@ -40,4 +41,10 @@ mod tests {
         let actual_result:String = super::run("(+ 2 2)".to_string());
         assert_eq!(actual_result, expected_result);
     }
+
+    #[test]
+    fn run_program() {
+        let actual_result:String = super::run("(def x 1) (+ x 2)".to_string());
+        assert_eq!(actual_result, "3");
+    }
 }